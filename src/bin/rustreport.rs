@@ -0,0 +1,136 @@
+//! Renders buffer files written by `Context::write_buffer` to PDF.
+//!
+//! ```text
+//! rustreport [-o <output.pdf>] [--font-path <dir>]... [--validate] [--no-subset] <buffer.txt>...
+//! ```
+//!
+//! Several buffer files are merged into one PDF, each starting on a new page.
+use rustreport::validate::Severity;
+use rustreport::Context;
+use std::fs::File;
+use std::io::BufReader;
+use std::process;
+
+const USAGE: &str = "usage: rustreport [-o <output.pdf>] [--font-path <dir>]... [--validate] \
+                     [--no-subset] <buffer.txt>...";
+
+struct Args {
+    inputs: Vec<String>,
+    output: Option<String>,
+    font_paths: Vec<String>,
+    validate: bool,
+    /// Embed whole fonts instead of the glyphs used.
+    no_subset: bool,
+}
+
+/// `None` for `-h`/`--help`.
+fn parse_args(args: impl Iterator<Item = String>) -> Result<Option<Args>, String> {
+    let mut inputs = Vec::new();
+    let mut output = None;
+    let mut font_paths = Vec::new();
+    let mut validate = false;
+    let mut no_subset = false;
+    let mut args = args;
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-o" | "--output" => {
+                output = Some(args.next().ok_or(format!("{} needs a file name", arg))?);
+            }
+            "--font-path" => {
+                font_paths.push(args.next().ok_or("--font-path needs a directory")?);
+            }
+            "--validate" => validate = true,
+            "--no-subset" => no_subset = true,
+            "-h" | "--help" => return Ok(None),
+            _ if arg.starts_with('-') => return Err(format!("unknown option: {}", arg)),
+            _ => inputs.push(arg),
+        }
+    }
+    if inputs.is_empty() {
+        return Err("no buffer file given".to_string());
+    }
+    Ok(Some(Args {
+        inputs,
+        output,
+        font_paths,
+        validate,
+        no_subset,
+    }))
+}
+
+/// `report.txt` -> `report.pdf`
+fn default_output(input: &str) -> String {
+    std::path::Path::new(input)
+        .with_extension("pdf")
+        .to_string_lossy()
+        .into_owned()
+}
+
+fn new_context(font_paths: &[String]) -> Context {
+    let mut context = Context::new();
+    for dir in font_paths.iter() {
+        context.add_font_path(dir);
+    }
+    context
+}
+
+fn validate(font_paths: &[String], input: &str) -> Result<(), String> {
+    let mut context = new_context(font_paths);
+    context
+        .read_buffer(input)
+        .map_err(|e| format!("{}: {}", input, e))?;
+    let issues = context.validate_buffer();
+    for issue in issues.iter() {
+        eprintln!("{}: {}", input, issue);
+    }
+    if issues.iter().any(|i| i.severity == Severity::Error) {
+        return Err(format!("{}: validation failed", input));
+    }
+    Ok(())
+}
+
+fn run(args: Args) -> Result<(), String> {
+    if args.validate {
+        let mut result = Ok(());
+        for input in args.inputs.iter() {
+            if let Err(e) = validate(&args.font_paths, input) {
+                result = Err(e);
+            }
+        }
+        return result;
+    }
+    // Each file ends its last page, and a P or FF repeated by a later
+    // file keeps the document, so the files simply convert one after another.
+    // convert_reader resets the text state, so no style leaks between files.
+    let mut context = new_context(&args.font_paths);
+    context.set_subset_fonts(!args.no_subset);
+    for input in args.inputs.iter() {
+        let file = File::open(input).map_err(|e| format!("{}: {}", input, e))?;
+        context
+            .convert_reader(BufReader::new(file))
+            .map_err(|e| format!("{}: {}", input, e))?;
+    }
+    let output = args
+        .output
+        .unwrap_or_else(|| default_output(&args.inputs[0]));
+    context.save(&output).map_err(|e| e.to_string())
+}
+
+fn main() {
+    let args = match parse_args(std::env::args().skip(1)) {
+        Ok(Some(args)) => args,
+        Ok(None) => {
+            println!("{}", USAGE);
+            return;
+        }
+        Err(e) => {
+            eprintln!("rustreport: {}", e);
+            eprintln!("{}", USAGE);
+            process::exit(2);
+        }
+    };
+    if let Err(e) = run(args) {
+        eprintln!("rustreport: {}", e);
+        process::exit(1);
+    }
+}
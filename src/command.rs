@@ -0,0 +1,946 @@
+use crate::error::{BufferError, ParseError};
+use crate::font::{FontStyle, TextDecoration};
+use crate::text::{TextAlign, TextFit};
+use crate::{PageOrientation, PageSize};
+use std::str::FromStr;
+
+/// Format version written in the `VER` header by `Context::write_buffer`.
+///
+/// * 1: no header, text fields are written as is (before escaping was added).
+/// * 2: `VER` header, text fields escaped with `escape_text`.
+///
+/// `Context::read_buffer` upgrades older versions and refuses newer ones, so
+/// a buffer archived today can be rendered by any later release.
+pub const BUFFER_FORMAT_VERSION: u32 = 2;
+
+/// One line of the intermediate buffer.
+///
+/// `serialize` produces the tab separated line written by the `set_*` and
+/// `write_*` methods (without the trailing newline) and `parse` reads it back.
+/// Text fields are escaped with `escape_text`, so they may contain any
+/// character including tabs and newlines.
+#[derive(Debug, Clone, PartialEq)]
+pub enum BufferCommand {
+    /// `P` page size and orientation. A `Custom` size is followed by its
+    /// width and height: `P\tCustom\t<orientation>\t<width>\t<height>`.
+    Page {
+        size: PageSize,
+        orientation: PageOrientation,
+    },
+    /// `PC` page size and orientation from the next page on. Same fields as
+    /// `P`. Takes effect at the next `NP`.
+    PageChange {
+        size: PageSize,
+        orientation: PageOrientation,
+    },
+    /// `PM` page size in mm.
+    PageMm { width: f32, height: f32 },
+    /// `FF` load font `{dir}/{name}-Regular.ttf`.
+    FontFile { name: String, dir: String },
+    /// `F` select a loaded font.
+    Font { name: String },
+    /// `FS` font size in pt.
+    FontSize(i32),
+    /// `FP` load a face of font `name` from `path`.
+    FontPath {
+        name: String,
+        style: FontStyle,
+        path: String,
+    },
+    /// `FM` load a face of font `name` from data registered with
+    /// `Context::add_font_bytes`.
+    FontMemory { name: String, style: FontStyle },
+    /// `FST` face of the current and following fonts.
+    FontStyle(FontStyle),
+    /// `FB` fonts used in order for characters `name` has no glyph for:
+    /// `FB\t<name>\t<fallback>...`. `FB\t<name>` alone removes them.
+    FontFallback {
+        name: String,
+        fallbacks: Vec<String>,
+    },
+    /// `CS` extra space after each character in pt.
+    CharSpacing(f64),
+    /// `WS` extra space after each space character in pt.
+    WordSpacing(f64),
+    /// `HS` horizontal scaling of text in percent, 100 is normal.
+    HorizontalScaling(f64),
+    /// `LD` distance between text box baselines in pt.
+    Leading(f64),
+    /// `TD` line drawn with the following text.
+    TextDecoration(TextDecoration),
+    /// `TC` text color.
+    TextColor { r: u8, g: u8, b: u8 },
+    /// `TG` text grey scale (0-100).
+    TextGreyScale(u8),
+    /// `OC` outline color.
+    OutlineColor { r: u8, g: u8, b: u8 },
+    /// `OS` outline thickness in pt.
+    OutlineThickness(f32),
+    /// `OG` outline grey scale (0-100).
+    OutlineGreyScale(u8),
+    /// `FC` fill color.
+    FillColor { r: u8, g: u8, b: u8 },
+    /// `FG` fill grey scale (0-100).
+    FillGreyScale(u8),
+    /// `TL` left aligned text.
+    TextLeft { x: f64, y: f64, text: String },
+    /// `TR` right aligned text.
+    TextRight { x: f64, y: f64, text: String },
+    /// `TM` text centred at `x`.
+    TextCenter { x: f64, y: f64, text: String },
+    /// `TJ` text justified between `x1` and `x2`: the words are spread so
+    /// the first starts at `x1` and the last ends at `x2`.
+    TextJustify {
+        x1: f64,
+        y1: f64,
+        x2: f64,
+        text: String,
+    },
+    /// `TF` one line of text made to fit `width` by `fit`, aligned in the
+    /// box from `x` to `x + width`.
+    TextFit {
+        x: f64,
+        y: f64,
+        width: f64,
+        align: TextAlign,
+        fit: TextFit,
+        text: String,
+    },
+    /// `TRT` text along a baseline turned `angle` degrees counter-clockwise
+    /// around its start `x`, `y`.
+    TextRotated {
+        x: f64,
+        y: f64,
+        angle: f64,
+        text: String,
+    },
+    /// `TV` vertical text (縦書き) in a column centred on `x`, starting at
+    /// `y` and running downwards.
+    TextVertical { x: f64, y: f64, text: String },
+    /// `TB` text wrapped to `width`. `y` is the baseline of the first line
+    /// and the following lines are `line_height` mm apart.
+    TextBox {
+        x: f64,
+        y: f64,
+        width: f64,
+        line_height: f64,
+        align: TextAlign,
+        text: String,
+    },
+    /// `DP` dash pattern.
+    DashPattern {
+        dash1: i32,
+        gap1: i32,
+        dash2: i32,
+        gap2: i32,
+    },
+    /// `RDP` reset dash pattern.
+    ResetDashPattern,
+    /// `LH` horizontal line.
+    LineHorizontal { x1: f64, y1: f64, x2: f64 },
+    /// `LHS` horizontal line the width of `text`, starting at `x1`.
+    LineHorizontalStrlen {
+        x1: f64,
+        y1: f64,
+        text: String,
+        offset_left: f64,
+        offset_right: f64,
+    },
+    /// `LHSR` horizontal line the width of `text`, ending at `x1`.
+    LineHorizontalRightStrlen {
+        x1: f64,
+        y1: f64,
+        text: String,
+        offset_left: f64,
+        offset_right: f64,
+    },
+    /// `LV` vertical line.
+    LineVertical { x1: f64, y1: f64, y2: f64 },
+    /// `L` line.
+    Line { x1: f64, y1: f64, x2: f64, y2: f64 },
+    /// `R` rectangle.
+    Rect {
+        x1: f64,
+        y1: f64,
+        x2: f64,
+        y2: f64,
+        fill: bool,
+    },
+    /// `I` image file.
+    Image {
+        x: f64,
+        y: f64,
+        w: f64,
+        h: f64,
+        path: String,
+    },
+    /// `NP` new page.
+    NewPage,
+    /// `RST` end of a report, before the page break to the next one: font
+    /// style, decoration, spacing, scaling, leading and fallbacks go back to
+    /// their defaults, and a `PC` the report left pending is dropped.
+    ResetState,
+    /// `FTS` start of the output of a footer band, which draws below
+    /// `footer_vpos` on purpose.
+    FooterStart,
+    /// `FTE` end of the output of a footer band.
+    FooterEnd,
+    /// `V` page number and total pages of the current report.
+    PageNumber { page: i32, total_page: i32 },
+    /// `VER` buffer format version and the rustreport version that wrote it.
+    Version { format: u32, library: String },
+}
+
+impl BufferCommand {
+    pub fn opcode(&self) -> &'static str {
+        match self {
+            BufferCommand::Page { .. } => "P",
+            BufferCommand::PageChange { .. } => "PC",
+            BufferCommand::PageMm { .. } => "PM",
+            BufferCommand::FontFile { .. } => "FF",
+            BufferCommand::Font { .. } => "F",
+            BufferCommand::FontSize(_) => "FS",
+            BufferCommand::FontPath { .. } => "FP",
+            BufferCommand::FontMemory { .. } => "FM",
+            BufferCommand::FontStyle(_) => "FST",
+            BufferCommand::FontFallback { .. } => "FB",
+            BufferCommand::CharSpacing(_) => "CS",
+            BufferCommand::WordSpacing(_) => "WS",
+            BufferCommand::HorizontalScaling(_) => "HS",
+            BufferCommand::Leading(_) => "LD",
+            BufferCommand::TextDecoration(_) => "TD",
+            BufferCommand::TextColor { .. } => "TC",
+            BufferCommand::TextGreyScale(_) => "TG",
+            BufferCommand::OutlineColor { .. } => "OC",
+            BufferCommand::OutlineThickness(_) => "OS",
+            BufferCommand::OutlineGreyScale(_) => "OG",
+            BufferCommand::FillColor { .. } => "FC",
+            BufferCommand::FillGreyScale(_) => "FG",
+            BufferCommand::TextLeft { .. } => "TL",
+            BufferCommand::TextRight { .. } => "TR",
+            BufferCommand::TextCenter { .. } => "TM",
+            BufferCommand::TextJustify { .. } => "TJ",
+            BufferCommand::TextBox { .. } => "TB",
+            BufferCommand::TextFit { .. } => "TF",
+            BufferCommand::TextRotated { .. } => "TRT",
+            BufferCommand::TextVertical { .. } => "TV",
+            BufferCommand::DashPattern { .. } => "DP",
+            BufferCommand::ResetDashPattern => "RDP",
+            BufferCommand::LineHorizontal { .. } => "LH",
+            BufferCommand::LineHorizontalStrlen { .. } => "LHS",
+            BufferCommand::LineHorizontalRightStrlen { .. } => "LHSR",
+            BufferCommand::LineVertical { .. } => "LV",
+            BufferCommand::Line { .. } => "L",
+            BufferCommand::Rect { .. } => "R",
+            BufferCommand::Image { .. } => "I",
+            BufferCommand::NewPage => "NP",
+            BufferCommand::ResetState => "RST",
+            BufferCommand::FooterStart => "FTS",
+            BufferCommand::FooterEnd => "FTE",
+            BufferCommand::PageNumber { .. } => "V",
+            BufferCommand::Version { .. } => "VER",
+        }
+    }
+
+    /// Text field of a text command or of `LHS`/`LHSR`.
+    pub fn text(&self) -> Option<&str> {
+        match self {
+            BufferCommand::TextLeft { text, .. }
+            | BufferCommand::TextRight { text, .. }
+            | BufferCommand::TextCenter { text, .. }
+            | BufferCommand::TextJustify { text, .. }
+            | BufferCommand::TextBox { text, .. }
+            | BufferCommand::TextFit { text, .. }
+            | BufferCommand::TextRotated { text, .. }
+            | BufferCommand::TextVertical { text, .. }
+            | BufferCommand::LineHorizontalStrlen { text, .. }
+            | BufferCommand::LineHorizontalRightStrlen { text, .. } => Some(text),
+            _ => None,
+        }
+    }
+
+    /// Same as `text`, for rewriting the field.
+    pub fn text_mut(&mut self) -> Option<&mut String> {
+        match self {
+            BufferCommand::TextLeft { text, .. }
+            | BufferCommand::TextRight { text, .. }
+            | BufferCommand::TextCenter { text, .. }
+            | BufferCommand::TextJustify { text, .. }
+            | BufferCommand::TextBox { text, .. }
+            | BufferCommand::TextFit { text, .. }
+            | BufferCommand::TextRotated { text, .. }
+            | BufferCommand::TextVertical { text, .. }
+            | BufferCommand::LineHorizontalStrlen { text, .. }
+            | BufferCommand::LineHorizontalRightStrlen { text, .. } => Some(text),
+            _ => None,
+        }
+    }
+
+    pub fn serialize(&self) -> String {
+        let op = self.opcode();
+        match self {
+            BufferCommand::Page { size, orientation }
+            | BufferCommand::PageChange { size, orientation } => match size {
+                PageSize::Custom {
+                    width_mm,
+                    height_mm,
+                } => format!(
+                    "{}\tCustom\t{}\t{}\t{}",
+                    op, orientation, width_mm, height_mm
+                ),
+                _ => format!("{}\t{}\t{}", op, size, orientation),
+            },
+            BufferCommand::PageMm { width, height } => format!("{}\t{}\t{}", op, width, height),
+            BufferCommand::FontFile { name, dir } => format!("{}\t{}\t{}", op, name, dir),
+            BufferCommand::Font { name } => format!("{}\t{}", op, name),
+            BufferCommand::FontSize(size) => format!("{}\t{}", op, size),
+            BufferCommand::FontPath { name, style, path } => {
+                format!("{}\t{}\t{}\t{}", op, name, style, path)
+            }
+            BufferCommand::FontMemory { name, style } => format!("{}\t{}\t{}", op, name, style),
+            BufferCommand::FontStyle(style) => format!("{}\t{}", op, style),
+            BufferCommand::FontFallback { name, fallbacks } if fallbacks.is_empty() => {
+                format!("{}\t{}", op, name)
+            }
+            BufferCommand::FontFallback { name, fallbacks } => {
+                format!("{}\t{}\t{}", op, name, fallbacks.join("\t"))
+            }
+            BufferCommand::TextColor { r, g, b }
+            | BufferCommand::OutlineColor { r, g, b }
+            | BufferCommand::FillColor { r, g, b } => format!("{}\t{}\t{}\t{}", op, r, g, b),
+            BufferCommand::TextGreyScale(g)
+            | BufferCommand::OutlineGreyScale(g)
+            | BufferCommand::FillGreyScale(g) => format!("{}\t{}", op, g),
+            BufferCommand::OutlineThickness(t) => format!("{}\t{}", op, t),
+            BufferCommand::TextDecoration(d) => format!("{}\t{}", op, d),
+            BufferCommand::CharSpacing(v)
+            | BufferCommand::WordSpacing(v)
+            | BufferCommand::HorizontalScaling(v)
+            | BufferCommand::Leading(v) => format!("{}\t{}", op, v),
+            BufferCommand::TextLeft { x, y, text }
+            | BufferCommand::TextRight { x, y, text }
+            | BufferCommand::TextCenter { x, y, text }
+            | BufferCommand::TextVertical { x, y, text } => {
+                format!("{}\t{}\t{}\t{}", op, x, y, escape_text(text))
+            }
+            BufferCommand::TextBox {
+                x,
+                y,
+                width,
+                line_height,
+                align,
+                text,
+            } => format!(
+                "{}\t{}\t{}\t{}\t{}\t{}\t{}",
+                op,
+                x,
+                y,
+                width,
+                line_height,
+                align,
+                escape_text(text)
+            ),
+            BufferCommand::TextFit {
+                x,
+                y,
+                width,
+                align,
+                fit,
+                text,
+            } => format!(
+                "{}\t{}\t{}\t{}\t{}\t{}\t{}",
+                op,
+                x,
+                y,
+                width,
+                align,
+                fit,
+                escape_text(text)
+            ),
+            BufferCommand::TextRotated { x, y, angle, text } => {
+                format!("{}\t{}\t{}\t{}\t{}", op, x, y, angle, escape_text(text))
+            }
+            BufferCommand::TextJustify { x1, y1, x2, text } => {
+                format!("{}\t{}\t{}\t{}\t{}", op, x1, y1, x2, escape_text(text))
+            }
+            BufferCommand::DashPattern {
+                dash1,
+                gap1,
+                dash2,
+                gap2,
+            } => format!("{}\t{}\t{}\t{}\t{}", op, dash1, gap1, dash2, gap2),
+            BufferCommand::ResetDashPattern
+            | BufferCommand::NewPage
+            | BufferCommand::ResetState
+            | BufferCommand::FooterStart
+            | BufferCommand::FooterEnd => op.to_string(),
+            BufferCommand::LineHorizontal { x1, y1, x2 } => {
+                format!("{}\t{}\t{}\t{}", op, x1, y1, x2)
+            }
+            BufferCommand::LineHorizontalStrlen {
+                x1,
+                y1,
+                text,
+                offset_left,
+                offset_right,
+            }
+            | BufferCommand::LineHorizontalRightStrlen {
+                x1,
+                y1,
+                text,
+                offset_left,
+                offset_right,
+            } => format!(
+                "{}\t{}\t{}\t{}\t{}\t{}",
+                op,
+                x1,
+                y1,
+                escape_text(text),
+                offset_left,
+                offset_right
+            ),
+            BufferCommand::LineVertical { x1, y1, y2 } => {
+                format!("{}\t{}\t{}\t{}", op, x1, y1, y2)
+            }
+            BufferCommand::Line { x1, y1, x2, y2 } => {
+                format!("{}\t{}\t{}\t{}\t{}", op, x1, y1, x2, y2)
+            }
+            BufferCommand::Rect {
+                x1,
+                y1,
+                x2,
+                y2,
+                fill,
+            } => format!(
+                "{}\t{}\t{}\t{}\t{}\t{}",
+                op,
+                x1,
+                y1,
+                x2,
+                y2,
+                if *fill { "Y" } else { "N" }
+            ),
+            BufferCommand::Image { x, y, w, h, path } => {
+                format!("{}\t{}\t{}\t{}\t{}\t{}", op, x, y, w, h, path)
+            }
+            BufferCommand::PageNumber { page, total_page } => {
+                format!("{}\tPAGE\t{}\tTOTALPAGE\t{}", op, page, total_page)
+            }
+            BufferCommand::Version { format, library } => {
+                format!("{}\t{}\t{}", op, format, library)
+            }
+        }
+    }
+
+    /// Parses one buffer line. A trailing `\n` or `\r\n` is ignored.
+    pub fn parse(line: &str) -> Result<BufferCommand, ParseError> {
+        let line = line.trim_end_matches('\n').trim_end_matches('\r');
+        if line.trim().is_empty() {
+            return Err(ParseError::Empty);
+        }
+        let mut f = Fields::new(line);
+        let command = match f.opcode {
+            "P" => {
+                let (size, orientation) = f.next_page_size()?;
+                BufferCommand::Page { size, orientation }
+            }
+            "PC" => {
+                let (size, orientation) = f.next_page_size()?;
+                BufferCommand::PageChange { size, orientation }
+            }
+            "PM" => BufferCommand::PageMm {
+                width: f.next_parse()?,
+                height: f.next_parse()?,
+            },
+            "FF" => BufferCommand::FontFile {
+                name: f.next_string()?,
+                dir: f.next_string()?,
+            },
+            "F" => BufferCommand::Font {
+                name: f.next_string()?,
+            },
+            "FS" => BufferCommand::FontSize(f.next_parse()?),
+            "FP" => BufferCommand::FontPath {
+                name: f.next_string()?,
+                style: f.next_parse()?,
+                path: f.next_string()?,
+            },
+            "FM" => BufferCommand::FontMemory {
+                name: f.next_string()?,
+                style: f.next_parse()?,
+            },
+            "FST" => BufferCommand::FontStyle(f.next_parse()?),
+            "FB" => BufferCommand::FontFallback {
+                name: f.next_string()?,
+                fallbacks: f.remaining_names()?,
+            },
+            "CS" => BufferCommand::CharSpacing(f.next_parse()?),
+            "WS" => BufferCommand::WordSpacing(f.next_parse()?),
+            "HS" => BufferCommand::HorizontalScaling(f.next_parse()?),
+            "LD" => BufferCommand::Leading(f.next_parse()?),
+            "TD" => BufferCommand::TextDecoration(f.next_parse()?),
+            "TC" => BufferCommand::TextColor {
+                r: f.next_parse()?,
+                g: f.next_parse()?,
+                b: f.next_parse()?,
+            },
+            "TG" => BufferCommand::TextGreyScale(f.next_parse()?),
+            "OC" => BufferCommand::OutlineColor {
+                r: f.next_parse()?,
+                g: f.next_parse()?,
+                b: f.next_parse()?,
+            },
+            "OS" => BufferCommand::OutlineThickness(f.next_parse()?),
+            "OG" => BufferCommand::OutlineGreyScale(f.next_parse()?),
+            "FC" => BufferCommand::FillColor {
+                r: f.next_parse()?,
+                g: f.next_parse()?,
+                b: f.next_parse()?,
+            },
+            "FG" => BufferCommand::FillGreyScale(f.next_parse()?),
+            "TL" => BufferCommand::TextLeft {
+                x: f.next_parse()?,
+                y: f.next_parse()?,
+                text: f.next_text()?,
+            },
+            "TR" => BufferCommand::TextRight {
+                x: f.next_parse()?,
+                y: f.next_parse()?,
+                text: f.next_text()?,
+            },
+            "TM" => BufferCommand::TextCenter {
+                x: f.next_parse()?,
+                y: f.next_parse()?,
+                text: f.next_text()?,
+            },
+            "TF" => BufferCommand::TextFit {
+                x: f.next_parse()?,
+                y: f.next_parse()?,
+                width: f.next_parse()?,
+                align: f.next_parse()?,
+                fit: f.next_parse()?,
+                text: f.next_text()?,
+            },
+            "TRT" => BufferCommand::TextRotated {
+                x: f.next_parse()?,
+                y: f.next_parse()?,
+                angle: f.next_parse()?,
+                text: f.next_text()?,
+            },
+            "TV" => BufferCommand::TextVertical {
+                x: f.next_parse()?,
+                y: f.next_parse()?,
+                text: f.next_text()?,
+            },
+            "TB" => BufferCommand::TextBox {
+                x: f.next_parse()?,
+                y: f.next_parse()?,
+                width: f.next_parse()?,
+                line_height: f.next_parse()?,
+                align: f.next_parse()?,
+                text: f.next_text()?,
+            },
+            "TJ" => BufferCommand::TextJustify {
+                x1: f.next_parse()?,
+                y1: f.next_parse()?,
+                x2: f.next_parse()?,
+                text: f.next_text()?,
+            },
+            "DP" => BufferCommand::DashPattern {
+                dash1: f.next_parse()?,
+                gap1: f.next_parse()?,
+                dash2: f.next_parse()?,
+                gap2: f.next_parse()?,
+            },
+            "RDP" => BufferCommand::ResetDashPattern,
+            "RST" => BufferCommand::ResetState,
+            "FTS" => BufferCommand::FooterStart,
+            "FTE" => BufferCommand::FooterEnd,
+            "LH" => BufferCommand::LineHorizontal {
+                x1: f.next_parse()?,
+                y1: f.next_parse()?,
+                x2: f.next_parse()?,
+            },
+            "LHS" => BufferCommand::LineHorizontalStrlen {
+                x1: f.next_parse()?,
+                y1: f.next_parse()?,
+                text: f.next_text()?,
+                offset_left: f.next_parse()?,
+                offset_right: f.next_parse()?,
+            },
+            "LHSR" => BufferCommand::LineHorizontalRightStrlen {
+                x1: f.next_parse()?,
+                y1: f.next_parse()?,
+                text: f.next_text()?,
+                offset_left: f.next_parse()?,
+                offset_right: f.next_parse()?,
+            },
+            "LV" => BufferCommand::LineVertical {
+                x1: f.next_parse()?,
+                y1: f.next_parse()?,
+                y2: f.next_parse()?,
+            },
+            "L" => BufferCommand::Line {
+                x1: f.next_parse()?,
+                y1: f.next_parse()?,
+                x2: f.next_parse()?,
+                y2: f.next_parse()?,
+            },
+            "R" => {
+                let x1 = f.next_parse()?;
+                let y1 = f.next_parse()?;
+                let x2 = f.next_parse()?;
+                let y2 = f.next_parse()?;
+                let fill = match f.next_str()? {
+                    "Y" => true,
+                    "N" => false,
+                    other => return Err(f.invalid(other)),
+                };
+                BufferCommand::Rect {
+                    x1,
+                    y1,
+                    x2,
+                    y2,
+                    fill,
+                }
+            }
+            "I" => BufferCommand::Image {
+                x: f.next_parse()?,
+                y: f.next_parse()?,
+                w: f.next_parse()?,
+                h: f.next_parse()?,
+                path: f.next_string()?,
+            },
+            "NP" => BufferCommand::NewPage,
+            "V" => {
+                f.next_keyword("PAGE")?;
+                let page = f.next_parse()?;
+                f.next_keyword("TOTALPAGE")?;
+                let total_page = f.next_parse()?;
+                BufferCommand::PageNumber { page, total_page }
+            }
+            "VER" => BufferCommand::Version {
+                format: f.next_parse()?,
+                library: f.next_string()?,
+            },
+            other => return Err(ParseError::UnknownOpcode(other.to_string())),
+        };
+        f.finish()?;
+        Ok(command)
+    }
+}
+
+/// Returns the format of a buffer whose first line is `first`.
+///
+/// Buffers without a `VER` header are format 1. Formats newer than
+/// `BUFFER_FORMAT_VERSION` are refused.
+pub fn buffer_format(first: &str) -> Result<u32, BufferError> {
+    let mut format = 1;
+    if first.starts_with("VER\t") {
+        match BufferCommand::parse(first) {
+            Ok(BufferCommand::Version { format: f, .. }) => format = f,
+            Ok(_) => {}
+            Err(e) => return Err(BufferError::InvalidHeader(e)),
+        }
+    }
+    if format > BUFFER_FORMAT_VERSION {
+        return Err(BufferError::UnsupportedVersion {
+            found: format,
+            supported: BUFFER_FORMAT_VERSION,
+        });
+    }
+    Ok(format)
+}
+
+/// Returns the `VER` header for buffers written by this version of rustreport.
+pub fn version_header() -> BufferCommand {
+    BufferCommand::Version {
+        format: BUFFER_FORMAT_VERSION,
+        library: env!("CARGO_PKG_VERSION").to_string(),
+    }
+}
+
+/// Rewrites a format 1 line as format 2.
+///
+/// Format 1 wrote text fields unescaped, so a tab in the text split it into
+/// several fields. Those fields are joined back and escaped. Replacing a
+/// `!!!name!!!` marker in format 1 also dropped the line feed after the
+/// text, gluing the next command to it, as in `TR\t97\t70\t¥83,545LH\t25\t74\t99`.
+/// Such a line is split again when the glued part parses as a command, so
+/// one format 1 line can give two or more lines.
+pub fn upgrade_v1_line(line: &str) -> Vec<String> {
+    let line = line.trim_end_matches('\n').trim_end_matches('\r');
+    let fields: Vec<&str> = line.split('\t').collect();
+    // index of the text field and number of fields following it
+    let (text_index, trailing) = match fields[0] {
+        "TL" | "TR" => (3, 0),
+        "LHS" | "LHSR" => (3, 2),
+        _ => return vec![line.to_string()],
+    };
+    if fields.len() < text_index + 1 + trailing {
+        return vec![line.to_string()];
+    }
+    if trailing == 0 {
+        if let Some(lines) = split_glued_line(&fields, text_index) {
+            return lines;
+        }
+    }
+    let text_end = fields.len() - trailing;
+    let text = fields[text_index..text_end].join("\t");
+    let mut upgraded: Vec<String> = fields[..text_index].iter().map(|s| s.to_string()).collect();
+    upgraded.push(escape_text(&text));
+    upgraded.extend(fields[text_end..].iter().map(|s| s.to_string()));
+    vec![upgraded.join("\t")]
+}
+
+/// Splits a format 1 line whose last field, the text, has the next command
+/// glued to it: a field from `text_index` on ends with an opcode, and that
+/// opcode with the fields after it upgrades to valid commands.
+fn split_glued_line(fields: &[&str], text_index: usize) -> Option<Vec<String>> {
+    for k in text_index..fields.len() - 1 {
+        let field = fields[k];
+        let opcode_start = match field
+            .char_indices()
+            .rev()
+            .take_while(|(_, c)| c.is_ascii_uppercase())
+            .last()
+        {
+            Some((i, _)) => i,
+            None => continue,
+        };
+        // the longest uppercase suffix first, e.g. LHS before HS
+        for (start, _) in field[opcode_start..].char_indices() {
+            let start = opcode_start + start;
+            let rest = std::iter::once(&field[start..])
+                .chain(fields[k + 1..].iter().copied())
+                .collect::<Vec<&str>>()
+                .join("\t");
+            let rest = upgrade_v1_line(&rest);
+            if rest.iter().all(|l| BufferCommand::parse(l).is_ok()) {
+                let mut first: Vec<String> =
+                    fields[..text_index].iter().map(|s| s.to_string()).collect();
+                let text = fields[text_index..k]
+                    .iter()
+                    .copied()
+                    .chain(std::iter::once(&field[..start]))
+                    .collect::<Vec<&str>>()
+                    .join("\t");
+                first.push(escape_text(&text));
+                let mut lines = vec![first.join("\t")];
+                lines.extend(rest);
+                return Some(lines);
+            }
+        }
+    }
+    None
+}
+
+/// Escapes text so that it fits in one field of a buffer line.
+///
+/// `\` is written as `\\`, tab as `\t`, line feed as `\n` and carriage
+/// return as `\r`. Every other character is written as is, so the
+/// `&#PAGETOTAL&#` and `!!!name!!!` markers still work; see `escape_markers`
+/// for user data that must not be replaced.
+pub fn escape_text(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '\\' => escaped.push_str("\\\\"),
+            '\t' => escaped.push_str("\\t"),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => escaped.push_str("\\r"),
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// Reverses `escape_text`. Returns `None` on an unknown or unterminated escape.
+pub fn unescape_text(field: &str) -> Option<String> {
+    let mut text = String::with_capacity(field.len());
+    let mut chars = field.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            text.push(c);
+            continue;
+        }
+        match chars.next()? {
+            '\\' => text.push('\\'),
+            't' => text.push('\t'),
+            'n' => text.push('\n'),
+            'r' => text.push('\r'),
+            _ => return None,
+        }
+    }
+    Some(text)
+}
+
+/// Replaced by the number of pages of the report in `exec`.
+pub const PAGE_TOTAL_MARKER: &str = "&#PAGETOTAL&#";
+
+/// Put in front of a marker by `escape_markers`. A Unicode noncharacter, so
+/// it never comes from user data and text nobody escaped draws unchanged.
+pub const MARKER_ESCAPE: char = '\u{FDD0}';
+
+/// Makes the `&#PAGETOTAL&#` and `!!!` in user data literal text, so that
+/// `exec` does not replace them, e.g. for a customer name from a database.
+///
+/// Each gets a `MARKER_ESCAPE` in front, which `convert` removes again
+/// before drawing (`unescape_markers`). Measure the text before escaping it.
+pub fn escape_markers(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some((start, marker)) = next_marker(rest) {
+        escaped.push_str(&rest[..start]);
+        escaped.push(MARKER_ESCAPE);
+        escaped.push_str(marker);
+        rest = &rest[start + marker.len()..];
+    }
+    escaped.push_str(rest);
+    escaped
+}
+
+/// Reverses `escape_markers`: the text as drawn. Only a `MARKER_ESCAPE`
+/// right before a marker is removed.
+pub fn unescape_markers(text: &str) -> String {
+    let mut unescaped = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some((start, marker)) = next_marker(rest) {
+        unescaped.push_str(&rest[..start]);
+        if unescaped.ends_with(MARKER_ESCAPE) {
+            unescaped.pop();
+        }
+        unescaped.push_str(marker);
+        rest = &rest[start + marker.len()..];
+    }
+    unescaped.push_str(rest);
+    unescaped
+}
+
+/// Replaces the `&#PAGETOTAL&#` markers in `text` that are not escaped by
+/// `escape_markers`. Works on buffer lines too.
+pub(crate) fn replace_page_total(text: &str, total: &str) -> String {
+    let mut replaced = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(start) = rest.find(PAGE_TOTAL_MARKER) {
+        replaced.push_str(&rest[..start]);
+        if replaced.ends_with(MARKER_ESCAPE) {
+            replaced.push_str(PAGE_TOTAL_MARKER);
+        } else {
+            replaced.push_str(total);
+        }
+        rest = &rest[start + PAGE_TOTAL_MARKER.len()..];
+    }
+    replaced.push_str(rest);
+    replaced
+}
+
+/// Start of the first `&#PAGETOTAL&#` or `!!!` in `text`.
+fn next_marker(text: &str) -> Option<(usize, &'static str)> {
+    [PAGE_TOTAL_MARKER, "!!!"]
+        .iter()
+        .filter_map(|marker| text.find(marker).map(|start| (start, *marker)))
+        .min_by_key(|(start, _)| *start)
+}
+
+/// Field cursor over one tab separated buffer line.
+struct Fields<'a> {
+    opcode: &'a str,
+    rest: std::str::Split<'a, char>,
+    index: usize,
+}
+
+impl<'a> Fields<'a> {
+    fn new(line: &'a str) -> Fields<'a> {
+        let mut rest = line.split('\t');
+        let opcode = rest.next().unwrap_or("");
+        Fields {
+            opcode,
+            rest,
+            index: 0,
+        }
+    }
+
+    fn next_str(&mut self) -> Result<&'a str, ParseError> {
+        self.index += 1;
+        self.rest.next().ok_or_else(|| ParseError::MissingField {
+            opcode: self.opcode.to_string(),
+            index: self.index,
+        })
+    }
+
+    fn next_string(&mut self) -> Result<String, ParseError> {
+        self.next_str().map(|s| s.to_string())
+    }
+
+    fn next_text(&mut self) -> Result<String, ParseError> {
+        let s = self.next_str()?;
+        unescape_text(s).ok_or_else(|| self.invalid(s))
+    }
+
+    fn next_parse<T: FromStr>(&mut self) -> Result<T, ParseError> {
+        let s = self.next_str()?;
+        s.parse::<T>().map_err(|_| self.invalid(s))
+    }
+
+    /// Size and orientation of `P` and `PC`.
+    fn next_page_size(&mut self) -> Result<(PageSize, PageOrientation), ParseError> {
+        let name = self.next_str()?;
+        let index = self.index;
+        let orientation = self.next_parse::<PageOrientation>()?;
+        let size = if name == "Custom" {
+            PageSize::Custom {
+                width_mm: self.next_parse()?,
+                height_mm: self.next_parse()?,
+            }
+        } else {
+            name.parse::<PageSize>()
+                .map_err(|_| ParseError::InvalidField {
+                    opcode: self.opcode.to_string(),
+                    index,
+                    value: name.to_string(),
+                })?
+        };
+        Ok((size, orientation))
+    }
+
+    /// The fields not read yet, none of which may be empty.
+    fn remaining_names(&mut self) -> Result<Vec<String>, ParseError> {
+        let mut names = Vec::new();
+        for s in self.rest.by_ref() {
+            self.index += 1;
+            if s.is_empty() {
+                return Err(self.invalid(s));
+            }
+            names.push(s.to_string());
+        }
+        Ok(names)
+    }
+
+    fn next_keyword(&mut self, keyword: &str) -> Result<(), ParseError> {
+        let s = self.next_str()?;
+        if s == keyword {
+            Ok(())
+        } else {
+            Err(self.invalid(s))
+        }
+    }
+
+    fn invalid(&self, value: &str) -> ParseError {
+        ParseError::InvalidField {
+            opcode: self.opcode.to_string(),
+            index: self.index,
+            value: value.to_string(),
+        }
+    }
+
+    fn finish(&mut self) -> Result<(), ParseError> {
+        match self.rest.next() {
+            None => Ok(()),
+            Some(_) => Err(ParseError::UnexpectedField {
+                opcode: self.opcode.to_string(),
+                index: self.index + 1,
+            }),
+        }
+    }
+}
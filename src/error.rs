@@ -0,0 +1,234 @@
+use std::{fmt, io};
+
+/// Error returned when a buffer line cannot be parsed into a `BufferCommand`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ParseError {
+    /// The line is empty or only contains whitespace.
+    Empty,
+    /// The first field is not a known opcode.
+    UnknownOpcode(String),
+    /// A required field is missing.
+    MissingField { opcode: String, index: usize },
+    /// A field could not be parsed into the expected type.
+    InvalidField {
+        opcode: String,
+        index: usize,
+        value: String,
+    },
+    /// The line has more fields than the opcode takes.
+    UnexpectedField { opcode: String, index: usize },
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ParseError::Empty => write!(f, "empty line"),
+            ParseError::UnknownOpcode(op) => write!(f, "unknown command: {}", op),
+            ParseError::MissingField { opcode, index } => {
+                write!(f, "{}: missing field {}", opcode, index)
+            }
+            ParseError::InvalidField {
+                opcode,
+                index,
+                value,
+            } => write!(
+                f,
+                "{}: invalid value {:?} in field {}",
+                opcode, value, index
+            ),
+            ParseError::UnexpectedField { opcode, index } => {
+                write!(f, "{}: unexpected field {}", opcode, index)
+            }
+        }
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+/// Error returned by `Context::read_buffer`.
+#[derive(Debug)]
+pub enum BufferError {
+    Io(io::Error),
+    /// The `VER` header line could not be parsed.
+    InvalidHeader(ParseError),
+    /// The buffer was written in a newer format than this version supports.
+    UnsupportedVersion {
+        found: u32,
+        supported: u32,
+    },
+}
+
+impl fmt::Display for BufferError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            BufferError::Io(e) => write!(f, "{}", e),
+            BufferError::InvalidHeader(e) => write!(f, "invalid buffer header: {}", e),
+            BufferError::UnsupportedVersion { found, supported } => write!(
+                f,
+                "buffer format version {} is newer than supported version {}",
+                found, supported
+            ),
+        }
+    }
+}
+
+impl std::error::Error for BufferError {}
+
+impl Clone for BufferError {
+    fn clone(&self) -> Self {
+        match self {
+            BufferError::Io(e) => BufferError::Io(io::Error::new(e.kind(), e.to_string())),
+            BufferError::InvalidHeader(e) => BufferError::InvalidHeader(e.clone()),
+            BufferError::UnsupportedVersion { found, supported } => {
+                BufferError::UnsupportedVersion {
+                    found: *found,
+                    supported: *supported,
+                }
+            }
+        }
+    }
+}
+
+impl From<io::Error> for BufferError {
+    fn from(e: io::Error) -> Self {
+        BufferError::Io(e)
+    }
+}
+
+/// Error returned by `Context::convert`.
+///
+/// `save` and `get_pdf_bytes` return it too: for the first failed `convert`
+/// instead of an incomplete PDF, and with line 0 and no opcode when the PDF
+/// cannot be written.
+#[derive(Debug, Clone)]
+pub struct ConvertError {
+    /// 1-based line number in `Context::buffer`.
+    pub line: usize,
+    pub opcode: String,
+    pub kind: ConvertErrorKind,
+}
+
+#[derive(Debug, Clone)]
+pub enum ConvertErrorKind {
+    Parse(ParseError),
+    /// Reading the input of `convert_reader` failed, or its `VER` header was
+    /// refused.
+    Buffer(BufferError),
+    /// `F` or a text command refers to a font not loaded with `FF`.
+    UnknownFont(String),
+    /// The font file could not be read or parsed.
+    FontFile {
+        path: String,
+        message: String,
+    },
+    /// A font face is registered again from another file than the one it
+    /// was loaded from.
+    FontConflict {
+        name: String,
+        loaded: String,
+        requested: String,
+    },
+    /// The image file could not be read or decoded.
+    Image {
+        path: String,
+        message: String,
+    },
+    /// The PDF could not be written.
+    Save(String),
+}
+
+impl fmt::Display for ConvertError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.line == 0 && self.opcode.is_empty() {
+            return write!(f, "{}", self.kind);
+        }
+        write!(f, "line {}: {}: {}", self.line, self.opcode, self.kind)
+    }
+}
+
+impl fmt::Display for ConvertErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ConvertErrorKind::Parse(e) => write!(f, "{}", e),
+            ConvertErrorKind::Buffer(e) => write!(f, "{}", e),
+            ConvertErrorKind::UnknownFont(name) => write!(f, "font {:?} is not loaded", name),
+            ConvertErrorKind::FontFile { path, message } => {
+                write!(f, "font file {}: {}", path, message)
+            }
+            ConvertErrorKind::FontConflict {
+                name,
+                loaded,
+                requested,
+            } => write!(
+                f,
+                "font {:?} is loaded from {}, not {}",
+                name, loaded, requested
+            ),
+            ConvertErrorKind::Image { path, message } => write!(f, "image {}: {}", path, message),
+            ConvertErrorKind::Save(message) => write!(f, "cannot save the PDF: {}", message),
+        }
+    }
+}
+
+impl std::error::Error for ConvertError {}
+
+impl std::error::Error for ConvertErrorKind {}
+
+/// Band kinds reported by `ReportError`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BandKind {
+    Detail,
+    PageHeader,
+    GroupHeader,
+    Summary,
+    Footer,
+    ReportSummary,
+}
+
+impl fmt::Display for BandKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            BandKind::Detail => write!(f, "Detail"),
+            BandKind::PageHeader => write!(f, "PageHeader"),
+            BandKind::GroupHeader => write!(f, "GroupHeader"),
+            BandKind::Summary => write!(f, "Summary"),
+            BandKind::Footer => write!(f, "Footer"),
+            BandKind::ReportSummary => write!(f, "ReportSummary"),
+        }
+    }
+}
+
+/// Error returned by `Context::exec`.
+#[derive(Debug)]
+pub enum ReportError {
+    /// `footer_vpos` is not set.
+    FooterPositionNotSet,
+    /// A band returned an error while processing input line `cur_line`.
+    Band {
+        band: BandKind,
+        cur_line: i32,
+        source: Box<dyn std::error::Error + Send + Sync>,
+    },
+}
+
+impl fmt::Display for ReportError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ReportError::FooterPositionNotSet => write!(f, "Footer position is not set"),
+            ReportError::Band {
+                band,
+                cur_line,
+                source,
+            } => write!(f, "{} at input line {}: {}", band, cur_line, source),
+        }
+    }
+}
+
+impl std::error::Error for ReportError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            ReportError::FooterPositionNotSet => None,
+            ReportError::Band { source, .. } => Some(source.as_ref()),
+        }
+    }
+}
@@ -1,5 +1,13 @@
 use crate::Context;
 use num_format::{Locale, ToFormattedString};
+
+/// Error returned by a band. Any error can be returned with `?`.
+pub type BandError = Box<dyn std::error::Error + Send + Sync>;
+pub type BandResult<T> = Result<T, BandError>;
+
+// `Context::exec` calls the `Try` traits. Every band implementing one of the
+// plain traits implements its `Try` trait too, through the adapters below;
+// implement the `Try` trait instead to return errors from a band.
 pub trait Detail {
     fn GetHeight(&self, context: &mut Context) -> f32;
     fn Execute(&self,context:&mut Context);
@@ -28,6 +36,90 @@ pub trait ReportSummary {
     fn GetHeight(&self,context:&mut Context)->f32;
     fn Execute(&self,context:&mut Context);
 }
+
+pub trait TryDetail {
+    fn TryGetHeight(&self, context: &mut Context) -> BandResult<f32>;
+    fn TryExecute(&self, context: &mut Context) -> BandResult<()>;
+    fn TryBreakCheckBefore(&self, _context: &mut Context) -> BandResult<i32> {
+        Ok(0)
+    }
+    fn TryBreakCheckAfter(&self, _context: &mut Context) -> BandResult<i32> {
+        Ok(0)
+    }
+}
+pub trait TryPageHeader {
+    fn TryExecute(&self, context: &mut Context) -> BandResult<()>;
+}
+pub trait TryGroupHeader {
+    fn TryGetHeight(&self, context: &mut Context) -> BandResult<f32>;
+    fn TryExecute(&self, context: &mut Context) -> BandResult<()>;
+}
+pub trait TrySummary {
+    fn TryGetHeight(&self, context: &mut Context) -> BandResult<f32>;
+    fn TryExecute(&self, context: &mut Context) -> BandResult<()>;
+}
+pub trait TryFooter {
+    fn TryExecute(&self, context: &mut Context) -> BandResult<()>;
+}
+pub trait TryReportSummary {
+    fn TryGetHeight(&self, context: &mut Context) -> BandResult<f32>;
+    fn TryExecute(&self, context: &mut Context) -> BandResult<()>;
+}
+
+impl<T: Detail + ?Sized> TryDetail for T {
+    fn TryGetHeight(&self, context: &mut Context) -> BandResult<f32> {
+        Ok(self.GetHeight(context))
+    }
+    fn TryExecute(&self, context: &mut Context) -> BandResult<()> {
+        self.Execute(context);
+        Ok(())
+    }
+    fn TryBreakCheckBefore(&self, context: &mut Context) -> BandResult<i32> {
+        Ok(self.BreakCheckBefore(context))
+    }
+    fn TryBreakCheckAfter(&self, context: &mut Context) -> BandResult<i32> {
+        Ok(self.BreakCheckAfter(context))
+    }
+}
+impl<T: PageHeader + ?Sized> TryPageHeader for T {
+    fn TryExecute(&self, context: &mut Context) -> BandResult<()> {
+        self.Execute(context);
+        Ok(())
+    }
+}
+impl<T: GroupHeader + ?Sized> TryGroupHeader for T {
+    fn TryGetHeight(&self, context: &mut Context) -> BandResult<f32> {
+        Ok(self.GetHeight(context))
+    }
+    fn TryExecute(&self, context: &mut Context) -> BandResult<()> {
+        self.Execute(context);
+        Ok(())
+    }
+}
+impl<T: Summary + ?Sized> TrySummary for T {
+    fn TryGetHeight(&self, context: &mut Context) -> BandResult<f32> {
+        Ok(self.GetHeight(context))
+    }
+    fn TryExecute(&self, context: &mut Context) -> BandResult<()> {
+        self.Execute(context);
+        Ok(())
+    }
+}
+impl<T: Footer + ?Sized> TryFooter for T {
+    fn TryExecute(&self, context: &mut Context) -> BandResult<()> {
+        self.Execute(context);
+        Ok(())
+    }
+}
+impl<T: ReportSummary + ?Sized> TryReportSummary for T {
+    fn TryGetHeight(&self, context: &mut Context) -> BandResult<f32> {
+        Ok(self.GetHeight(context))
+    }
+    fn TryExecute(&self, context: &mut Context) -> BandResult<()> {
+        self.Execute(context);
+        Ok(())
+    }
+}
 pub fn f64_roundedd2(f:f64)->f64{
     (f * 100.0).round() / 100.0
 }
@@ -0,0 +1,263 @@
+use crate::error::ConvertErrorKind;
+use genpdfrev::fonts::FontFamily;
+use printpdf::ParsedFont;
+use std::fmt;
+use std::str::FromStr;
+
+/// Face of a font family. A family registered with
+/// `set_font_dir_and_name(dir, name)` is read from `{dir}/{name}-{style}.ttf`,
+/// e.g. `Roboto-BoldItalic.ttf`; `set_font_file` and `set_font_bytes`
+/// register each face explicitly. A missing face falls back to `Regular`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum FontStyle {
+    #[default]
+    Regular,
+    Bold,
+    Italic,
+    BoldItalic,
+}
+
+impl FontStyle {
+    pub const ALL: [FontStyle; 4] = [
+        FontStyle::Regular,
+        FontStyle::Bold,
+        FontStyle::Italic,
+        FontStyle::BoldItalic,
+    ];
+
+    /// File name of this face of `font_name`.
+    pub fn file_name(&self, font_name: &str) -> String {
+        format!("{}-{}.ttf", font_name, self)
+    }
+
+    /// Key of this face of `font_name` in `Context::font_files`: the font
+    /// name for `Regular`, `{name}-{style}` otherwise.
+    pub fn key(&self, font_name: &str) -> String {
+        match self {
+            FontStyle::Regular => font_name.to_string(),
+            _ => format!("{}-{}", font_name, self),
+        }
+    }
+
+    /// This face of a genpdf font family.
+    pub(crate) fn face<T>(&self, family: &FontFamily<T>) -> T
+    where
+        T: Clone,
+    {
+        match self {
+            FontStyle::Regular => family.regular.clone(),
+            FontStyle::Bold => family.bold.clone(),
+            FontStyle::Italic => family.italic.clone(),
+            FontStyle::BoldItalic => family.bold_italic.clone(),
+        }
+    }
+}
+
+/// Written as the file suffix, e.g. `Bold`, in the `FST` buffer command.
+impl fmt::Display for FontStyle {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            FontStyle::Regular => write!(f, "Regular"),
+            FontStyle::Bold => write!(f, "Bold"),
+            FontStyle::Italic => write!(f, "Italic"),
+            FontStyle::BoldItalic => write!(f, "BoldItalic"),
+        }
+    }
+}
+
+impl FromStr for FontStyle {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "Regular" => Ok(FontStyle::Regular),
+            "Bold" => Ok(FontStyle::Bold),
+            "Italic" => Ok(FontStyle::Italic),
+            "BoldItalic" => Ok(FontStyle::BoldItalic),
+            _ => Err(format!("unknown font style: {}", s)),
+        }
+    }
+}
+
+/// Where a font face is read from.
+#[derive(Debug, Clone)]
+pub enum FontSource {
+    File(String),
+    /// Font data compiled into the binary, e.g. with `include_bytes!`.
+    Bytes(&'static [u8]),
+}
+
+impl FontSource {
+    pub(crate) fn read(&self) -> Result<Vec<u8>, ConvertErrorKind> {
+        match self {
+            FontSource::File(path) => std::fs::read(path).map_err(|e| ConvertErrorKind::FontFile {
+                path: path.clone(),
+                message: e.to_string(),
+            }),
+            FontSource::Bytes(bytes) => Ok(bytes.to_vec()),
+        }
+    }
+
+    /// Same file or same static data, without comparing the bytes.
+    pub(crate) fn same(&self, other: &FontSource) -> bool {
+        self.key() == other.key()
+    }
+
+    /// Identifies the file by its canonical path, so `dir/x.ttf`,
+    /// `dir//x.ttf` and an absolute path to it are the same, or the static
+    /// data. A file that does not exist keeps its path, normalized.
+    pub(crate) fn key(&self) -> String {
+        match self {
+            FontSource::File(path) => std::fs::canonicalize(path)
+                .unwrap_or_else(|_| {
+                    std::path::Path::new(path)
+                        .components()
+                        .collect::<std::path::PathBuf>()
+                })
+                .display()
+                .to_string(),
+            FontSource::Bytes(bytes) => format!("<memory {:p} {}>", bytes.as_ptr(), bytes.len()),
+        }
+    }
+}
+
+/// The path, or `<memory>` for in-memory data. Used in errors.
+impl fmt::Display for FontSource {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            FontSource::File(path) => write!(f, "{}", path),
+            FontSource::Bytes(_) => write!(f, "<memory>"),
+        }
+    }
+}
+
+/// Decoration line of text, set by `set_text_decoration`.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum TextDecoration {
+    #[default]
+    None,
+    Underline,
+    /// Two underlines, as under grand totals in accounting.
+    DoubleUnderline,
+    Strikethrough,
+}
+
+/// Written as the variant name in the `TD` buffer command.
+impl fmt::Display for TextDecoration {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            TextDecoration::None => write!(f, "None"),
+            TextDecoration::Underline => write!(f, "Underline"),
+            TextDecoration::DoubleUnderline => write!(f, "DoubleUnderline"),
+            TextDecoration::Strikethrough => write!(f, "Strikethrough"),
+        }
+    }
+}
+
+impl FromStr for TextDecoration {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "None" => Ok(TextDecoration::None),
+            "Underline" => Ok(TextDecoration::Underline),
+            "DoubleUnderline" => Ok(TextDecoration::DoubleUnderline),
+            "Strikethrough" => Ok(TextDecoration::Strikethrough),
+            _ => Err(format!("unknown text decoration: {}", s)),
+        }
+    }
+}
+
+/// Position and thickness of the underline and strikethrough of a font face,
+/// in em. As in the font's `post` and `OS/2` tables, a position is the top of
+/// the line above the baseline, so the underline's is negative.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct LineMetrics {
+    pub underline_position: f64,
+    pub underline_thickness: f64,
+    pub strikeout_position: f64,
+    pub strikeout_thickness: f64,
+}
+
+impl Default for LineMetrics {
+    fn default() -> Self {
+        LineMetrics {
+            underline_position: -0.1,
+            underline_thickness: 0.05,
+            strikeout_position: 0.26,
+            strikeout_thickness: 0.05,
+        }
+    }
+}
+
+impl LineMetrics {
+    /// Metrics of a TrueType or OpenType font. Values the font does not
+    /// provide keep their defaults.
+    pub fn from_font(bytes: &[u8]) -> LineMetrics {
+        match ParsedFont::from_bytes(bytes, 0) {
+            Some(font) => LineMetrics::from_parsed(&font),
+            None => LineMetrics::default(),
+        }
+    }
+
+    /// Metrics of a font parsed by `printpdf`. It reads `head` and `OS/2`
+    /// but not the underline fields of `post`, which are taken from the
+    /// font data here.
+    pub fn from_parsed(font: &ParsedFont) -> LineMetrics {
+        let mut metrics = LineMetrics::default();
+        let font_metrics = &font.font_metrics;
+        if font_metrics.units_per_em == 0 {
+            return metrics;
+        }
+        let em = |v: i16| v as f64 / font_metrics.units_per_em as f64;
+        if let Some((position, thickness)) = post_underline(&font.original_bytes) {
+            if thickness > 0 {
+                metrics.underline_position = em(position);
+                metrics.underline_thickness = em(thickness);
+            }
+        }
+        if font_metrics.y_strikeout_size > 0 {
+            metrics.strikeout_position = em(font_metrics.y_strikeout_position);
+            metrics.strikeout_thickness = em(font_metrics.y_strikeout_size);
+        }
+        metrics
+    }
+
+    /// Distance of the middle of the underline below the baseline, in em.
+    pub fn underline_offset(&self) -> f64 {
+        self.underline_thickness / 2.0 - self.underline_position
+    }
+
+    /// Distance of the middle of the strikethrough below the baseline, in em.
+    pub fn strikeout_offset(&self) -> f64 {
+        self.strikeout_thickness / 2.0 - self.strikeout_position
+    }
+}
+
+/// `underlinePosition` and `underlineThickness` of the `post` table.
+fn post_underline(bytes: &[u8]) -> Option<(i16, i16)> {
+    let post = sfnt_table(bytes, b"post")?;
+    Some((read_i16(post, 8)?, read_i16(post, 10)?))
+}
+
+fn read_i16(data: &[u8], offset: usize) -> Option<i16> {
+    let b = data.get(offset..offset + 2)?;
+    Some(i16::from_be_bytes([b[0], b[1]]))
+}
+
+fn read_u32(data: &[u8], offset: usize) -> Option<u32> {
+    let b = data.get(offset..offset + 4)?;
+    Some(u32::from_be_bytes([b[0], b[1], b[2], b[3]]))
+}
+
+/// A table of the first font in sfnt data, from the table directory.
+fn sfnt_table<'a>(bytes: &'a [u8], tag: &[u8; 4]) -> Option<&'a [u8]> {
+    let count = read_i16(bytes, 4)? as u16 as usize;
+    (0..count).find_map(|i| {
+        let record = 12 + i * 16;
+        if bytes.get(record..record + 4)? != tag {
+            return None;
+        }
+        let offset = read_u32(bytes, record + 8)? as usize;
+        let length = read_u32(bytes, record + 12)? as usize;
+        bytes.get(offset..offset + length)
+    })
+}
@@ -1,19 +1,32 @@
+pub mod command;
+pub mod error;
 pub mod exec;
+pub mod font;
+pub mod text;
+pub mod units;
+pub mod validate;
 
+pub use command::BufferCommand;
+pub use error::{BandKind, BufferError, ConvertError, ConvertErrorKind, ParseError, ReportError};
+pub use font::{FontSource, FontStyle, LineMetrics, TextDecoration};
 use genpdfrev;
-use genpdfrev::fonts::{Font, FontCache, FontFamily};
+use genpdfrev::fonts::{Font, FontCache, FontData, FontFamily};
 use genpdfrev::render::Page;
 use image::{DynamicImage, GenericImageView};
 use printpdf::ops::*;
 use printpdf::{
-    BlendMode, Cmyk, ExtendedGraphicsStateBuilder, FontId, Greyscale, Line, LineCapStyle,
-    LineDashPattern, LineJoinStyle, Mm, PaintMode, ParsedFont, PdfDocument, Point, Polygon, Pt,
-    RawImage, WindingOrder, XObjectTransform,
+    BlendMode, Cmyk, ExtendedGraphicsStateBuilder, FontId, Greyscale, LayerInternalId, Line,
+    LineCapStyle, LineDashPattern, LineJoinStyle, Mm, PaintMode, ParsedFont, PdfDocument, Point,
+    Polygon, Pt, RawImage, WindingOrder, XObjectId, XObjectTransform,
 };
-use printpdf::{Color, Rgb};
+use printpdf::{Color, PdfSaveOptions, Rgb, TextMatrix};
 use serde_json::Value;
 use std::collections::HashMap;
+use std::io::{BufRead, Write};
+use std::str::FromStr;
 use std::{fmt, fs};
+pub use text::{TextAlign, TextFit};
+pub use units::Length;
 
 pub struct Context {
     pub page_height: f32,
@@ -21,14 +34,73 @@ pub struct Context {
     pub pages: Vec<PdfPage>,
     pub font_files: HashMap<String, FontId>,
     pub font_name: String,
+    /// Face of the current font, set by `set_font_style`.
+    pub font_style: FontStyle,
+    /// Where each registered face is read from, by `FontStyle::key`.
+    pub font_faces: HashMap<String, FontSource>,
+    /// In-memory font data for `FM` lines, by `FontStyle::key`.
+    pub font_bytes: HashMap<String, &'static [u8]>,
+    /// Fonts tried in order for characters the key font has no glyph for.
+    pub font_fallbacks: HashMap<String, Vec<String>>,
+    /// Underline and strikethrough metrics of each loaded face, by
+    /// `FontStyle::key`.
+    pub line_metrics: HashMap<String, LineMetrics>,
+    /// Regular face of each loaded font, for glyph coverage.
+    pub parsed_fonts: HashMap<String, ParsedFont>,
     pub font_cache: Option<FontCache>,
     pub font_id: FontId,
     pub font_size: i32,
     pub buffer: Vec<String>,
     pub doc: PdfDocument,
+    /// Images added to `doc` by path, with their size in pixels, so an image
+    /// drawn on every page is embedded once.
+    pub images: HashMap<String, (XObjectId, u32, u32)>,
+    /// Graphics and text layers of `doc`, shared by all its pages.
+    pub layers: Option<(LayerInternalId, LayerInternalId)>,
     pub opttext: Vec<Op>,
     pub optgraphic: Vec<Op>,
     pub genpdffonts: HashMap<String, FontFamily<Font>>,
+    /// Families added to `font_cache`, keyed by the sources of their faces,
+    /// so a font registered again from the same files is not added twice.
+    pub font_families: HashMap<String, FontFamily<Font>>,
+    /// Directories searched when an `FF` font directory does not contain the font.
+    pub font_paths: Vec<String>,
+    /// Fonts that could not be loaded when set, with the buffer line of their
+    /// command. Bands cannot measure text in them. `convert` loads them
+    /// again, also from `font_paths`, and fails at that line if it still
+    /// cannot.
+    pub font_errors: Vec<ConvertError>,
+    /// The first error of `convert` or `convert_reader`, returned by `save`
+    /// and `get_pdf_bytes` instead of an incomplete PDF.
+    pub convert_error: Option<ConvertError>,
+    /// Page width and height in mm from the next page on, set by
+    /// `set_next_page` (or `PC` while converting) and applied at the next
+    /// new page.
+    pub next_page_size: Option<(f32, f32)>,
+    /// Page margins. Coordinates given to `write_*` are relative to the top
+    /// left of the printable area.
+    pub margins: Margins,
+    /// Height reserved for the footer at the bottom of the printable area.
+    /// When set, `exec` derives `footer_vpos` from it.
+    pub footer_height: Option<f64>,
+    /// Line drawn with text, set by `set_text_decoration`.
+    pub text_decoration: TextDecoration,
+    /// Extra space after each character in pt.
+    pub char_spacing: f64,
+    /// Extra space after each space character in pt.
+    pub word_spacing: f64,
+    /// Horizontal scaling of text in percent.
+    pub horizontal_scaling: f64,
+    /// Distance between `write_text_box` baselines in pt, overriding
+    /// `line_spacing`.
+    pub leading: Option<f64>,
+    /// Alignment of the lines of `write_text_box`.
+    pub text_align: TextAlign,
+    /// Distance between the baselines of `write_text_box` lines, as a
+    /// multiple of the font size.
+    pub line_spacing: f64,
+    /// Embed only the glyphs the document uses when saving. On by default.
+    pub subset_fonts: bool,
     pub input: Vec<Vec<String>>,
     pub cur_line: i32,
     pub cur_vpos: f64,
@@ -39,13 +111,13 @@ pub struct Context {
     pub page_report: i32,
     pub page_total: i32,
     pub flags: HashMap<String, bool>,
-    pub detail: Vec<Box<dyn exec::Detail>>,
-    pub page_header: Vec<Box<dyn exec::PageHeader>>,
-    pub group_header: Vec<Box<dyn exec::GroupHeader>>,
+    pub detail: Vec<Box<dyn exec::TryDetail>>,
+    pub page_header: Vec<Box<dyn exec::TryPageHeader>>,
+    pub group_header: Vec<Box<dyn exec::TryGroupHeader>>,
     pub max_level: i32,
-    pub footor: Vec<Box<dyn exec::Footer>>,
-    pub summary: Vec<Box<dyn exec::Summary>>,
-    pub report_summary: Vec<Box<dyn exec::ReportSummary>>,
+    pub footor: Vec<Box<dyn exec::TryFooter>>,
+    pub summary: Vec<Box<dyn exec::TrySummary>>,
+    pub report_summary: Vec<Box<dyn exec::TryReportSummary>>,
 }
 impl Clone for Context {
     fn clone(&self) -> Self {
@@ -55,14 +127,37 @@ impl Clone for Context {
             pages: self.pages.clone(),
             font_files: self.font_files.clone(),
             font_name: self.font_name.clone(),
+            font_style: self.font_style,
+            font_faces: self.font_faces.clone(),
+            font_bytes: self.font_bytes.clone(),
+            font_fallbacks: self.font_fallbacks.clone(),
+            parsed_fonts: self.parsed_fonts.clone(),
+            line_metrics: self.line_metrics.clone(),
+            text_decoration: self.text_decoration,
             font_cache: self.font_cache.clone(),
             font_id: self.font_id.clone(),
             font_size: self.font_size,
             buffer: self.buffer.clone(),
             doc: self.doc.clone(),
+            images: self.images.clone(),
+            layers: self.layers.clone(),
             opttext: self.opttext.clone(),
             optgraphic: self.optgraphic.clone(),
             genpdffonts: self.genpdffonts.clone(),
+            font_families: self.font_families.clone(),
+            font_paths: self.font_paths.clone(),
+            font_errors: self.font_errors.clone(),
+            convert_error: self.convert_error.clone(),
+            next_page_size: self.next_page_size,
+            margins: self.margins.clone(),
+            footer_height: self.footer_height,
+            char_spacing: self.char_spacing,
+            word_spacing: self.word_spacing,
+            horizontal_scaling: self.horizontal_scaling,
+            leading: self.leading,
+            text_align: self.text_align,
+            line_spacing: self.line_spacing,
+            subset_fonts: self.subset_fonts,
             input: self.input.clone(),
             cur_line: self.cur_line,
             cur_vpos: self.cur_vpos,
@@ -83,7 +178,37 @@ impl Clone for Context {
         }
     }
 }
-#[derive(PartialEq, Clone)]
+/// Page margins in mm. `new` and `uniform` accept any `Length`.
+#[derive(Debug, PartialEq, Clone, Default)]
+pub struct Margins {
+    pub top: f64,
+    pub right: f64,
+    pub bottom: f64,
+    pub left: f64,
+}
+
+impl Margins {
+    pub fn new(
+        top: impl Into<Length>,
+        right: impl Into<Length>,
+        bottom: impl Into<Length>,
+        left: impl Into<Length>,
+    ) -> Margins {
+        Margins {
+            top: top.into().mm(),
+            right: right.into().mm(),
+            bottom: bottom.into().mm(),
+            left: left.into().mm(),
+        }
+    }
+    /// The same margin on all four sides.
+    pub fn uniform(margin: impl Into<Length>) -> Margins {
+        let margin = margin.into();
+        Margins::new(margin, margin, margin, margin)
+    }
+}
+
+#[derive(Debug, PartialEq, Clone)]
 pub enum PageOrientation {
     Portrait,
     Landscape,
@@ -98,17 +223,138 @@ impl fmt::Display for PageOrientation {
     }
 }
 
-#[derive(PartialEq, Clone)]
+impl FromStr for PageOrientation {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "Portrait" => Ok(PageOrientation::Portrait),
+            "Landscape" => Ok(PageOrientation::Landscape),
+            _ => Err(format!("unknown page orientation: {}", s)),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Clone)]
 pub enum PageSize {
+    A3,
     A4,
+    A5,
+    /// JIS B4 (257 x 364 mm)
+    B4Jis,
+    /// JIS B5 (182 x 257 mm)
+    B5Jis,
+    /// ISO B4 (250 x 353 mm)
+    B4Iso,
+    /// ISO B5 (176 x 250 mm)
+    B5Iso,
     Letter,
+    Legal,
+    Tabloid,
+    /// DL envelope (110 x 220 mm)
+    EnvelopeDl,
+    /// C4 envelope (229 x 324 mm)
+    EnvelopeC4,
+    /// C5 envelope (162 x 229 mm)
+    EnvelopeC5,
+    /// C6 envelope (114 x 162 mm)
+    EnvelopeC6,
+    /// US No. 10 envelope (105 x 241 mm)
+    Envelope10,
+    /// 長形3号 (120 x 235 mm)
+    EnvelopeNaga3,
+    /// 角形2号 (240 x 332 mm)
+    EnvelopeKaku2,
+    /// Any size in mm, given in portrait orientation.
+    Custom {
+        width_mm: f32,
+        height_mm: f32,
+    },
+}
+
+impl PageSize {
+    /// Width and height in mm for the given orientation.
+    pub fn dimensions(&self, orientation: &PageOrientation) -> (f32, f32) {
+        let (w, h) = match self {
+            PageSize::A3 => (297.0, 420.0),
+            PageSize::A4 => (210.0, 297.0),
+            PageSize::A5 => (148.0, 210.0),
+            PageSize::B4Jis => (257.0, 364.0),
+            PageSize::B5Jis => (182.0, 257.0),
+            PageSize::B4Iso => (250.0, 353.0),
+            PageSize::B5Iso => (176.0, 250.0),
+            PageSize::Letter => (216.0, 279.0),
+            PageSize::Legal => (216.0, 356.0),
+            PageSize::Tabloid => (279.0, 432.0),
+            PageSize::EnvelopeDl => (110.0, 220.0),
+            PageSize::EnvelopeC4 => (229.0, 324.0),
+            PageSize::EnvelopeC5 => (162.0, 229.0),
+            PageSize::EnvelopeC6 => (114.0, 162.0),
+            PageSize::Envelope10 => (105.0, 241.0),
+            PageSize::EnvelopeNaga3 => (120.0, 235.0),
+            PageSize::EnvelopeKaku2 => (240.0, 332.0),
+            PageSize::Custom {
+                width_mm,
+                height_mm,
+            } => (*width_mm, *height_mm),
+        };
+        match orientation {
+            PageOrientation::Portrait => (w, h),
+            PageOrientation::Landscape => (h, w),
+        }
+    }
 }
 
+/// Writes the name used in the `P` buffer command. `Custom` sizes are
+/// written as `Custom`; their dimensions follow the orientation field.
 impl fmt::Display for PageSize {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match self {
+            PageSize::A3 => write!(f, "A3"),
             PageSize::A4 => write!(f, "A4"),
+            PageSize::A5 => write!(f, "A5"),
+            PageSize::B4Jis => write!(f, "B4Jis"),
+            PageSize::B5Jis => write!(f, "B5Jis"),
+            PageSize::B4Iso => write!(f, "B4Iso"),
+            PageSize::B5Iso => write!(f, "B5Iso"),
             PageSize::Letter => write!(f, "Letter"),
+            PageSize::Legal => write!(f, "Legal"),
+            PageSize::Tabloid => write!(f, "Tabloid"),
+            PageSize::EnvelopeDl => write!(f, "EnvelopeDl"),
+            PageSize::EnvelopeC4 => write!(f, "EnvelopeC4"),
+            PageSize::EnvelopeC5 => write!(f, "EnvelopeC5"),
+            PageSize::EnvelopeC6 => write!(f, "EnvelopeC6"),
+            PageSize::Envelope10 => write!(f, "Envelope10"),
+            PageSize::EnvelopeNaga3 => write!(f, "EnvelopeNaga3"),
+            PageSize::EnvelopeKaku2 => write!(f, "EnvelopeKaku2"),
+            PageSize::Custom { .. } => write!(f, "Custom"),
+        }
+    }
+}
+
+/// Parses the named sizes. `Custom` needs its dimensions and is parsed by
+/// `BufferCommand::parse`.
+impl FromStr for PageSize {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "A3" => Ok(PageSize::A3),
+            "A4" => Ok(PageSize::A4),
+            "A5" => Ok(PageSize::A5),
+            "B4Jis" => Ok(PageSize::B4Jis),
+            "B5Jis" => Ok(PageSize::B5Jis),
+            "B4Iso" => Ok(PageSize::B4Iso),
+            "B5Iso" => Ok(PageSize::B5Iso),
+            "Letter" => Ok(PageSize::Letter),
+            "Legal" => Ok(PageSize::Legal),
+            "Tabloid" => Ok(PageSize::Tabloid),
+            "EnvelopeDl" => Ok(PageSize::EnvelopeDl),
+            "EnvelopeC4" => Ok(PageSize::EnvelopeC4),
+            "EnvelopeC5" => Ok(PageSize::EnvelopeC5),
+            "EnvelopeC6" => Ok(PageSize::EnvelopeC6),
+            "Envelope10" => Ok(PageSize::Envelope10),
+            "EnvelopeNaga3" => Ok(PageSize::EnvelopeNaga3),
+            "EnvelopeKaku2" => Ok(PageSize::EnvelopeKaku2),
+            _ => Err(format!("unknown page size: {}", s)),
         }
     }
 }
@@ -120,17 +366,40 @@ impl Context {
             pages: Vec::new(),
             font_files: HashMap::new(),
             font_name: String::from(""),
+            font_style: FontStyle::Regular,
+            font_faces: HashMap::new(),
+            font_bytes: HashMap::new(),
+            font_fallbacks: HashMap::new(),
+            parsed_fonts: HashMap::new(),
+            line_metrics: HashMap::new(),
+            text_decoration: TextDecoration::None,
             font_cache: None,
             font_id: FontId::new(),
             opttext: Vec::new(),
             optgraphic: Vec::new(),
             genpdffonts: HashMap::new(),
+            font_families: HashMap::new(),
+            font_paths: Vec::new(),
+            font_errors: Vec::new(),
+            convert_error: None,
+            next_page_size: None,
+            margins: Margins::default(),
+            footer_height: None,
+            char_spacing: 0.0,
+            word_spacing: 0.0,
+            horizontal_scaling: 100.0,
+            leading: None,
+            text_align: TextAlign::Left,
+            line_spacing: 1.2,
+            subset_fonts: true,
             font_size: 0,
             cur_line: 0,
             cur_vpos: 0.0,
             footer_vpos: 0.0,
             buffer: Vec::new(),
             doc: PdfDocument::new("PDF"),
+            images: HashMap::new(),
+            layers: None,
             input: Vec::new(),
             sum_work: HashMap::new(),
             vers: HashMap::new(),
@@ -150,514 +419,1651 @@ impl Context {
 }
 impl Context {
     pub fn set_page(&mut self, ps: PageSize, pt: PageOrientation) {
-        let (w, h) = match (ps.clone(), pt.clone()) {
-            (PageSize::A4, PageOrientation::Portrait) => (210.0, 297.0),
-            (PageSize::A4, PageOrientation::Landscape) => (297.0, 210.0),
-            (PageSize::Letter, PageOrientation::Portrait) => (216.0, 279.0),
-            (PageSize::Letter, PageOrientation::Landscape) => (279.0, 216.0),
-        };
-        self.page_height = h as f32;
-        self.page_width = w as f32;
-        self.buffer
-            .push(format!("P\t{}\t{}\n", ps.to_string(), pt.to_string(),));
+        let (w, h) = ps.dimensions(&pt);
+        self.page_height = h;
+        self.page_width = w;
+        self.push_command(BufferCommand::Page {
+            size: ps,
+            orientation: pt,
+        });
+    }
+
+    pub fn set_margins(&mut self, margins: Margins) {
+        self.margins = margins;
+    }
+    /// Sets `footer_height`. `exec` then sets `footer_vpos` to
+    /// `printable_height() - height`, so it follows the page size.
+    pub fn set_footer_height(&mut self, height: impl Into<Length>) {
+        self.footer_height = Some(height.into().mm());
+    }
+    /// Width of the page inside the margins.
+    pub fn printable_width(&self) -> f64 {
+        self.page_width as f64 - self.margins.left - self.margins.right
+    }
+    /// Height of the page inside the margins.
+    pub fn printable_height(&self) -> f64 {
+        self.page_height as f64 - self.margins.top - self.margins.bottom
+    }
+    /// Changes the page size and orientation from the next page on, e.g.
+    /// landscape appendix pages in a portrait report. `page_width`,
+    /// `page_height` and `footer_vpos` change at the next `new_page`, so page
+    /// headers of the new page already see the new size.
+    pub fn set_next_page(&mut self, ps: PageSize, pt: PageOrientation) {
+        self.next_page_size = Some(ps.dimensions(&pt));
+        self.push_command(BufferCommand::PageChange {
+            size: ps,
+            orientation: pt,
+        });
     }
 
     pub fn set_page_by_mm(&mut self, h: f64, w: f64) {
         self.page_height = h as f32;
         self.page_width = w as f32;
-        self.buffer
-            .push(format!("PM\t{}\t{}\n", self.page_width, self.page_height));
+        self.push_command(BufferCommand::PageMm {
+            width: self.page_width,
+            height: self.page_height,
+        });
     }
 }
 impl Context {
     pub fn get_buffer(&self) -> String {
         self.buffer.join("")
     }
+    /// Appends a command to the buffer.
+    pub fn push_command(&mut self, command: BufferCommand) {
+        self.buffer.push(command.serialize() + "\n");
+    }
+    /// Parses the buffer into commands. Blank lines are skipped.
+    pub fn commands(&self) -> Result<Vec<BufferCommand>, ParseError> {
+        let mut commands = Vec::new();
+        for line in self.buffer.iter() {
+            match BufferCommand::parse(line) {
+                Ok(command) => commands.push(command),
+                Err(ParseError::Empty) => {}
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(commands)
+    }
+    /// Registers the font `{fontDir}/{fontName}-Regular.ttf` and makes it
+    /// current. Its metrics are loaded right away so bands can measure text
+    /// during `exec`; if the file cannot be read here, the error is added to
+    /// `font_errors`.
     pub fn set_font_dir_and_name(&mut self, fontDir: &str, fontName: &str) {
         self.font_name = String::from(fontName);
-        self.buffer.push(format!("FF\t{}\t{}\n", fontName, fontDir));
+        let loaded = self.load_font_metrics(fontDir, fontName);
+        self.push_font_command(
+            loaded,
+            BufferCommand::FontFile {
+                name: fontName.to_string(),
+                dir: fontDir.to_string(),
+            },
+        );
+    }
+    /// Registers a face of `font_name` read from `path`, whatever its file
+    /// name. Registering the `Regular` face makes the font current, like
+    /// `set_font_dir_and_name`.
+    pub fn set_font_file(&mut self, font_name: &str, style: FontStyle, path: &str) {
+        let loaded = self.register_font_face(font_name, style, FontSource::File(path.to_string()));
+        if style == FontStyle::Regular {
+            self.font_name = font_name.to_string();
+        }
+        self.push_font_command(
+            loaded,
+            BufferCommand::FontPath {
+                name: font_name.to_string(),
+                style,
+                path: path.to_string(),
+            },
+        );
+    }
+    /// Registers a face of `font_name` from font data in memory, e.g.
+    /// `include_bytes!`, so a binary can ship its fonts. The buffer only
+    /// records the name (`FM`); converting it in another program needs the
+    /// same data registered there with `add_font_bytes`.
+    pub fn set_font_bytes(&mut self, font_name: &str, style: FontStyle, bytes: &'static [u8]) {
+        self.add_font_bytes(font_name, style, bytes);
+        let loaded = self.register_font_face(font_name, style, FontSource::Bytes(bytes));
+        if style == FontStyle::Regular {
+            self.font_name = font_name.to_string();
+        }
+        self.push_font_command(
+            loaded,
+            BufferCommand::FontMemory {
+                name: font_name.to_string(),
+                style,
+            },
+        );
+    }
+    /// Appends a font command and records in `font_errors` why its font
+    /// could not be loaded.
+    fn push_font_command(&mut self, loaded: Result<(), ConvertErrorKind>, command: BufferCommand) {
+        let opcode = command.opcode().to_string();
+        self.push_command(command);
+        if let Err(kind) = loaded {
+            self.font_errors.push(ConvertError {
+                line: self.buffer.len(),
+                opcode,
+                kind,
+            });
+        }
+    }
+    /// Provides the data of an `FM` font face to `convert`.
+    pub fn add_font_bytes(&mut self, font_name: &str, style: FontStyle, bytes: &'static [u8]) {
+        self.font_bytes.insert(style.key(font_name), bytes);
+    }
+    /// Adds a directory searched for fonts at convert time.
+    pub fn add_font_path(&mut self, dir: &str) {
+        self.font_paths.push(dir.to_string());
     }
     pub fn set_font(&mut self, fontName: &str) {
         self.font_name = String::from(fontName);
-        self.buffer.push(format!("F\t{}\n", self.font_name));
+        self.push_command(BufferCommand::Font {
+            name: self.font_name.clone(),
+        });
+    }
+    /// Switches to the bold, italic or bold italic face of the current font.
+    /// Stays in effect when the font changes.
+    pub fn set_font_style(&mut self, style: FontStyle) {
+        self.font_style = style;
+        self.push_command(BufferCommand::FontStyle(style));
+    }
+    /// Sets the fonts used, in order, for characters `font_name` has no glyph
+    /// for, e.g. a Japanese font behind a Latin one. Text is split into runs
+    /// by glyph coverage and each run is drawn and measured with its font.
+    /// The fallback fonts must be loaded with `set_font_dir_and_name`. An
+    /// empty list removes the fallbacks of `font_name`.
+    pub fn set_font_fallback(&mut self, font_name: &str, fallbacks: &[&str]) {
+        let fallbacks: Vec<String> = fallbacks.iter().map(|f| f.to_string()).collect();
+        if fallbacks.is_empty() {
+            self.font_fallbacks.remove(font_name);
+        } else {
+            self.font_fallbacks
+                .insert(font_name.to_string(), fallbacks.clone());
+        }
+        self.push_command(BufferCommand::FontFallback {
+            name: font_name.to_string(),
+            fallbacks,
+        });
     }
     pub fn set_font_size(&mut self, font_size: i32) {
         self.font_size = i32::from(font_size);
-        self.buffer.push(format!("FS\t{}\n", self.font_size));
+        self.push_command(BufferCommand::FontSize(self.font_size));
+    }
+    /// Extra space after each character, negative to tighten. Taken into
+    /// account by `string_width` and the aligned text commands.
+    pub fn set_char_spacing(&mut self, spacing: impl Into<Length>) {
+        self.char_spacing = spacing.into().pt();
+        self.push_command(BufferCommand::CharSpacing(self.char_spacing));
+    }
+    /// Extra space after each space character.
+    pub fn set_word_spacing(&mut self, spacing: impl Into<Length>) {
+        self.word_spacing = spacing.into().pt();
+        self.push_command(BufferCommand::WordSpacing(self.word_spacing));
+    }
+    /// Horizontal scaling of text in percent, e.g. 80 for condensed figures.
+    pub fn set_horizontal_scaling(&mut self, percent: f64) {
+        self.horizontal_scaling = percent;
+        self.push_command(BufferCommand::HorizontalScaling(percent));
+    }
+    /// Distance between the baselines of `write_text_box` lines, instead of
+    /// `line_spacing` times the font size.
+    pub fn set_leading(&mut self, leading: impl Into<Length>) {
+        let leading = leading.into().pt();
+        self.leading = Some(leading);
+        self.push_command(BufferCommand::Leading(leading));
+    }
+    /// Underlines, double underlines or strikes through the following text.
+    /// Position and thickness come from the font. Not applied to vertical
+    /// text.
+    pub fn set_text_decoration(&mut self, decoration: TextDecoration) {
+        self.text_decoration = decoration;
+        self.push_command(BufferCommand::TextDecoration(decoration));
     }
     pub fn set_text_color(&mut self, r: u8, g: u8, b: u8) {
-        self.buffer.push(format!("TC\t{}\t{}\t{}\n", r, g, b));
+        self.push_command(BufferCommand::TextColor { r, g, b });
     }
     pub fn set_text_greyScale(&mut self, g: u8) {
-        self.buffer.push(format!("TG\t{}\n", g));
+        self.push_command(BufferCommand::TextGreyScale(g));
     }
     pub fn set_outline_color(&mut self, r: u8, g: u8, b: u8) {
-        self.buffer.push(format!("OC\t{}\t{}\t{}\n", r, g, b));
+        self.push_command(BufferCommand::OutlineColor { r, g, b });
     }
+    /// Line width in points.
     pub fn set_outline_thickness(&mut self, t: f32) {
-        self.buffer.push(format!("OS\t{}\n", t));
+        self.push_command(BufferCommand::OutlineThickness(t));
+    }
+    /// Line width in any unit, e.g. `Length::Mm(0.1)`.
+    pub fn set_outline_width(&mut self, width: impl Into<Length>) {
+        self.set_outline_thickness(width.into().pt() as f32);
     }
     pub fn set_outline_greyScale(&mut self, g: u8) {
-        self.buffer.push(format!("OG\t{}\n", g));
+        self.push_command(BufferCommand::OutlineGreyScale(g));
     }
     pub fn set_fill_color(&mut self, r: u8, g: u8, b: u8) {
-        self.buffer.push(format!("FC\t{}\t{}\t{}\n", r, g, b));
+        self.push_command(BufferCommand::FillColor { r, g, b });
     }
     pub fn set_fill_greyScale(&mut self, g: u8) {
-        self.buffer.push(format!("FG\t{}\n", g));
+        self.push_command(BufferCommand::FillGreyScale(g));
+    }
+    /// Page x of a printable-area x. The buffer always holds page coordinates.
+    fn abs_x(&self, x: impl Into<Length>) -> f64 {
+        x.into().mm() + self.margins.left
+    }
+    /// Page y of a printable-area y.
+    fn abs_y(&self, y: impl Into<Length>) -> f64 {
+        y.into().mm() + self.margins.top
+    }
+    pub fn write_text(&mut self, x: impl Into<Length>, y: impl Into<Length>, text: &str) {
+        self.push_command(BufferCommand::TextLeft {
+            x: self.abs_x(x),
+            y: self.abs_y(y),
+            text: text.to_string(),
+        });
+    }
+    pub fn write_text_right(&mut self, x: impl Into<Length>, y: impl Into<Length>, text: &str) {
+        self.push_command(BufferCommand::TextRight {
+            x: self.abs_x(x),
+            y: self.abs_y(y),
+            text: text.to_string(),
+        });
+    }
+    /// Writes `text` centred at `x`.
+    pub fn write_text_center(&mut self, x: impl Into<Length>, y: impl Into<Length>, text: &str) {
+        self.push_command(BufferCommand::TextCenter {
+            x: self.abs_x(x),
+            y: self.abs_y(y),
+            text: text.to_string(),
+        });
+    }
+    /// Writes `text` justified between `x1` and `x2`. The space between words
+    /// is widened (or narrowed) so the last word ends at `x2`. A single word is
+    /// left aligned at `x1`.
+    pub fn write_text_justify(
+        &mut self,
+        x1: impl Into<Length>,
+        y1: impl Into<Length>,
+        x2: impl Into<Length>,
+        text: &str,
+    ) {
+        self.push_command(BufferCommand::TextJustify {
+            x1: self.abs_x(x1),
+            y1: self.abs_y(y1),
+            x2: self.abs_x(x2),
+            text: text.to_string(),
+        });
+    }
+    /// Writes one line of text that fits `width`: cut with `…` or written
+    /// smaller, as chosen by `fit`. It is aligned in the box from `x` to
+    /// `x + width` by `text_align` (`Justify` is left aligned).
+    pub fn write_text_fit(
+        &mut self,
+        x: impl Into<Length>,
+        y: impl Into<Length>,
+        width: impl Into<Length>,
+        fit: TextFit,
+        text: &str,
+    ) {
+        self.push_command(BufferCommand::TextFit {
+            x: self.abs_x(x),
+            y: self.abs_y(y),
+            width: width.into().mm(),
+            align: self.text_align,
+            fit,
+            text: text.to_string(),
+        });
+    }
+    /// Writes text along a baseline turned `angle` degrees counter-clockwise
+    /// around `x`, `y`, e.g. 90 for a label reading bottom to top.
+    pub fn write_text_rotated(
+        &mut self,
+        x: impl Into<Length>,
+        y: impl Into<Length>,
+        angle: f64,
+        text: &str,
+    ) {
+        self.push_command(BufferCommand::TextRotated {
+            x: self.abs_x(x),
+            y: self.abs_y(y),
+            angle,
+            text: text.to_string(),
+        });
+    }
+    /// Writes vertical text (縦書き) in a column centred on `x`, from `y`
+    /// downwards. CJK characters stand upright one em apart, punctuation
+    /// uses its vertical form and other text is turned on its side.
+    pub fn write_text_vertical(&mut self, x: impl Into<Length>, y: impl Into<Length>, text: &str) {
+        self.push_command(BufferCommand::TextVertical {
+            x: self.abs_x(x),
+            y: self.abs_y(y),
+            text: text.to_string(),
+        });
     }
-    pub fn write_text(&mut self, x: f64, y: f64, text: &str) {
-        self.buffer.push(format!("TL\t{}\t{}\t{}\n", x, y, text));
+    /// Length in mm of `write_text_vertical` with the same text.
+    pub fn measure_text_vertical(&self, text: &str) -> Result<f64, ConvertErrorKind> {
+        Ok(self.vertical_pieces(text)?.iter().map(|p| p.2).sum())
+    }
+    pub fn set_text_align(&mut self, align: TextAlign) {
+        self.text_align = align;
+    }
+    pub fn set_line_spacing(&mut self, line_spacing: f64) {
+        self.line_spacing = line_spacing;
+    }
+    /// Distance between text box lines in mm for the current font size.
+    fn line_height(&self) -> f64 {
+        let leading = self
+            .leading
+            .unwrap_or(self.font_size as f64 * self.line_spacing);
+        Length::Pt(leading).mm()
+    }
+    /// Writes `text` wrapped to `width`, with the current font size,
+    /// `line_spacing` and `text_align`. `y` is the baseline of the first line.
+    /// Lines break at spaces, between CJK characters and at newlines; the
+    /// wrapping is done by `convert` with the font metrics.
+    pub fn write_text_box(
+        &mut self,
+        x: impl Into<Length>,
+        y: impl Into<Length>,
+        width: impl Into<Length>,
+        text: &str,
+    ) {
+        self.push_command(BufferCommand::TextBox {
+            x: self.abs_x(x),
+            y: self.abs_y(y),
+            width: width.into().mm(),
+            line_height: self.line_height(),
+            align: self.text_align,
+            text: text.to_string(),
+        });
     }
-    pub fn write_text_right(&mut self, x: f64, y: f64, text: &str) {
-        self.buffer.push(format!("TR\t{}\t{}\t{}\n", x, y, text));
+    /// Height in mm of `write_text_box` with the same `width` and `text`,
+    /// i.e. number of lines times the line height. Use it in `GetHeight` so
+    /// `page_break_check` sees the real height. Needs the metrics of the
+    /// current font.
+    pub fn measure_text_box(
+        &self,
+        width: impl Into<Length>,
+        text: &str,
+    ) -> Result<f64, ConvertErrorKind> {
+        let lines = text::wrap_text(text, width.into().mm(), &mut |s| self.string_width(s))?;
+        Ok(lines.len() as f64 * self.line_height())
     }
     pub fn set_dash_pattern(&mut self, dash1: i32, gap1: i32, dash2: i32, gap2: i32) {
-        self.buffer
-            .push(format!("DP\t{}\t{}\t{}\t{}\n", dash1, gap1, dash2, gap2));
+        self.push_command(BufferCommand::DashPattern {
+            dash1,
+            gap1,
+            dash2,
+            gap2,
+        });
     }
     pub fn reset_dash_pattern(&mut self) {
-        self.buffer.push("RDP\n".to_string());
+        self.push_command(BufferCommand::ResetDashPattern);
     }
-    pub fn write_line_horizontal(&mut self, x1: f64, y1: f64, x2: f64) {
-        self.buffer.push(format!("LH\t{}\t{}\t{}\n", x1, y1, x2));
+    pub fn write_line_horizontal(
+        &mut self,
+        x1: impl Into<Length>,
+        y1: impl Into<Length>,
+        x2: impl Into<Length>,
+    ) {
+        self.push_command(BufferCommand::LineHorizontal {
+            x1: self.abs_x(x1),
+            y1: self.abs_y(y1),
+            x2: self.abs_x(x2),
+        });
     }
     pub fn write_line_horizontal_strlen(
         &mut self,
-        x1: f64,
-        y1: f64,
+        x1: impl Into<Length>,
+        y1: impl Into<Length>,
         s: String,
-        ofsettl: f64,
-        ofsettr: f64,
+        ofsettl: impl Into<Length>,
+        ofsettr: impl Into<Length>,
     ) {
-        self.buffer.push(format!(
-            "LHS\t{}\t{}\t{}\t{}\t{}\n",
-            x1, y1, s, ofsettl, ofsettr
-        ));
+        self.push_command(BufferCommand::LineHorizontalStrlen {
+            x1: self.abs_x(x1),
+            y1: self.abs_y(y1),
+            text: s,
+            offset_left: ofsettl.into().mm(),
+            offset_right: ofsettr.into().mm(),
+        });
     }
     pub fn write_line_horizontal_right_strlen(
         &mut self,
-        x1: f64,
-        y1: f64,
+        x1: impl Into<Length>,
+        y1: impl Into<Length>,
         s: String,
-        ofsettl: f64,
-        ofsettr: f64,
+        ofsettl: impl Into<Length>,
+        ofsettr: impl Into<Length>,
     ) {
-        self.buffer.push(format!(
-            "LHSR\t{}\t{}\t{}\t{}\t{}\n",
-            x1, y1, s, ofsettl, ofsettr
-        ));
+        self.push_command(BufferCommand::LineHorizontalRightStrlen {
+            x1: self.abs_x(x1),
+            y1: self.abs_y(y1),
+            text: s,
+            offset_left: ofsettl.into().mm(),
+            offset_right: ofsettr.into().mm(),
+        });
     }
-    pub fn write_line_vertical(&mut self, x1: f64, y1: f64, y2: f64) {
-        self.buffer.push(format!("LV\t{}\t{}\t{}\n", x1, y1, y2));
+    pub fn write_line_vertical(
+        &mut self,
+        x1: impl Into<Length>,
+        y1: impl Into<Length>,
+        y2: impl Into<Length>,
+    ) {
+        self.push_command(BufferCommand::LineVertical {
+            x1: self.abs_x(x1),
+            y1: self.abs_y(y1),
+            y2: self.abs_y(y2),
+        });
     }
-    pub fn write_line(&mut self, x1: f64, y1: f64, x2: f64, y2: f64) {
-        self.buffer
-            .push(format!("L\t{}\t{}\t{}\t{}\n", x1, y1, x2, y2));
+    pub fn write_line(
+        &mut self,
+        x1: impl Into<Length>,
+        y1: impl Into<Length>,
+        x2: impl Into<Length>,
+        y2: impl Into<Length>,
+    ) {
+        self.push_command(BufferCommand::Line {
+            x1: self.abs_x(x1),
+            y1: self.abs_y(y1),
+            x2: self.abs_x(x2),
+            y2: self.abs_y(y2),
+        });
     }
-    pub fn write_rect(&mut self, x1: f64, y1: f64, x2: f64, y2: f64, fill: bool) {
-        if fill {
-            self.buffer
-                .push(format!("R\t{}\t{}\t{}\t{}\t{}\n", x1, y1, x2, y2, "Y"));
-        } else {
-            self.buffer
-                .push(format!("R\t{}\t{}\t{}\t{}\t{}\n", x1, y1, x2, y2, "N"));
-        }
+    pub fn write_rect(
+        &mut self,
+        x1: impl Into<Length>,
+        y1: impl Into<Length>,
+        x2: impl Into<Length>,
+        y2: impl Into<Length>,
+        fill: bool,
+    ) {
+        self.push_command(BufferCommand::Rect {
+            x1: self.abs_x(x1),
+            y1: self.abs_y(y1),
+            x2: self.abs_x(x2),
+            y2: self.abs_y(y2),
+            fill,
+        });
     }
-    pub fn write_image(&mut self, x: f64, y: f64, w: f64, h: f64, img: &str) {
-        self.buffer
-            .push(format!("I\t{}\t{}\t{}\t{}\t{}\n", x, y, w, h, img));
+    pub fn write_image(
+        &mut self,
+        x: impl Into<Length>,
+        y: impl Into<Length>,
+        w: impl Into<Length>,
+        h: impl Into<Length>,
+        img: &str,
+    ) {
+        self.push_command(BufferCommand::Image {
+            x: self.abs_x(x),
+            y: self.abs_y(y),
+            w: w.into().mm(),
+            h: h.into().mm(),
+            path: img.to_string(),
+        });
     }
     pub fn new_page(&mut self) {
-        self.buffer.push("NP\n".to_string());
+        self.push_command(BufferCommand::NewPage);
+        if let Some((w, h)) = self.next_page_size.take() {
+            // keep the footer at the same distance from the bottom
+            if self.footer_vpos > 0.0 {
+                self.footer_vpos += h as f64 - self.page_height as f64;
+            }
+            self.page_width = w;
+            self.page_height = h;
+        }
+    }
+    /// Appends the buffer of another report so both convert into one PDF.
+    ///
+    /// The appended report starts on a new page. Its page numbers and
+    /// `&#PAGETOTAL&#` values were resolved by its own `exec`, so they are
+    /// kept as is. Fonts it loads again with `FF` are shared. Font style,
+    /// decoration, spacing, scaling, leading and fallbacks go back to their
+    /// defaults, so nothing carries over from the previous report.
+    pub fn append_buffer(&mut self, other: &Context) {
+        self.append_lines(other.buffer.iter().cloned());
+    }
+    /// Same as `append_buffer` for a file written by `write_buffer`.
+    pub fn append_buffer_file(&mut self, filename: &str) -> Result<(), BufferError> {
+        let mut other = Context::new();
+        other.read_buffer(filename)?;
+        self.append_lines(other.buffer.into_iter());
+        Ok(())
     }
-    pub fn write_buffer(&mut self, filename: &str) {
+    /// Merges several reports into one buffer. See `append_buffer`.
+    pub fn merge(reports: &[Context]) -> Context {
+        let mut context = Context::new();
+        for report in reports.iter() {
+            context.append_buffer(report);
+        }
+        context
+    }
+    fn append_lines(&mut self, lines: impl Iterator<Item = String>) {
+        let has_content = self
+            .buffer
+            .iter()
+            .any(|l| !l.trim().is_empty() && !l.starts_with("VER\t"));
+        if has_content {
+            // before the page break, so a pending PC does not size the first
+            // page of the appended report
+            self.push_command(BufferCommand::ResetState);
+            self.push_command(BufferCommand::NewPage);
+        }
+        self.buffer
+            .extend(lines.filter(|l| !l.starts_with("VER\t")));
+    }
+    pub fn write_buffer(&mut self, filename: &str) -> std::io::Result<()> {
         //self.bufferの内容をファイルに書き出す
-        let mut txt = self.get_buffer();
+        let mut txt = String::new();
+        if !self.buffer.first().is_some_and(|l| l.starts_with("VER\t")) {
+            txt = command::version_header().serialize() + "\n";
+        }
+        txt.push_str(&self.get_buffer());
         //これをfilenameに書き出す
-        std::fs::write(filename, txt).expect("Unable to write file");
+        std::fs::write(filename, txt)
     }
-    pub fn read_buffer(&mut self, filename: &str) {
+    /// Reads a buffer written by `write_buffer`.
+    ///
+    /// Buffers without a `VER` header (format 1) are upgraded to the current
+    /// format. Buffers written in a newer format are refused. A `VER` header
+    /// is kept as is as the first line, so line numbers match the file and
+    /// `write_buffer` keeps the rustreport version that wrote the buffer.
+    pub fn read_buffer(&mut self, filename: &str) -> Result<(), BufferError> {
         //filenameの内容をself.bufferに読み込む
-        let txt = std::fs::read_to_string(filename).expect("Unable to read file");
-        self.buffer = txt.split("\n").map(|x| x.to_string()).collect();
+        let txt = std::fs::read_to_string(filename)?;
+        let mut lines = txt.lines().peekable();
+        let first = lines.peek().copied().unwrap_or("");
+        let format = command::buffer_format(first)?;
+        let mut header = Vec::new();
+        if first.starts_with("VER\t") {
+            header.push(first.to_string() + "\n");
+            lines.next();
+        }
+        self.buffer = header;
+        for x in lines {
+            if format == 1 {
+                self.buffer
+                    .extend(command::upgrade_v1_line(x).into_iter().map(|l| l + "\n"));
+            } else {
+                self.buffer.push(x.to_string() + "\n");
+            }
+        }
+        Ok(())
     }
 }
 impl Context {
-    pub fn getWidthOfString(&self, text: String) -> f64 {
-        println!("{}", &self.font_name);
-        println!("{:?}", self.genpdffonts);
-        let width: genpdfrev::Mm = self
-            .genpdffonts
-            .get(&self.font_name)
-            .unwrap()
-            .regular
-            .str_width(
-                &self.font_cache.clone().unwrap(),
-                text.as_str(),
-                self.font_size as u8,
+    /// Returns `dir` if it contains the font, otherwise the first of
+    /// `font_paths` that does. Falls back to `dir`.
+    fn resolve_font_dir(&self, dir: &str, font_name: &str) -> String {
+        let file = format!("{}-Regular.ttf", font_name);
+        std::iter::once(dir)
+            .chain(self.font_paths.iter().map(|p| p.as_str()))
+            .find(|d| std::path::Path::new(d).join(&file).is_file())
+            .unwrap_or(dir)
+            .to_string()
+    }
+    /// Registers the faces of a font found in `dir` by the
+    /// `{name}-{style}.ttf` convention and loads its metrics, unless the font
+    /// is already loaded from the same directory.
+    fn load_font_metrics(&mut self, dir: &str, font_name: &str) -> Result<(), ConvertErrorKind> {
+        let dir = self.resolve_font_dir(dir, font_name);
+        if self.genpdffonts.contains_key(font_name) {
+            let regular = format!("{}/{}", dir, FontStyle::Regular.file_name(font_name));
+            return self.check_font_source(font_name, &FontSource::File(regular));
+        }
+        for style in FontStyle::ALL {
+            let path = format!("{}/{}", dir, style.file_name(font_name));
+            if style == FontStyle::Regular || std::path::Path::new(&path).is_file() {
+                self.font_faces
+                    .insert(style.key(font_name), FontSource::File(path));
+            }
+        }
+        self.load_font_family(font_name)
+    }
+    /// Records where a face of a font is read from and reloads the metrics
+    /// of the font.
+    fn register_font_face(
+        &mut self,
+        font_name: &str,
+        style: FontStyle,
+        source: FontSource,
+    ) -> Result<(), ConvertErrorKind> {
+        let key = style.key(font_name);
+        if self.genpdffonts.contains_key(font_name) && self.font_faces.contains_key(&key) {
+            return self.check_font_source(&key, &source);
+        }
+        self.font_faces.insert(key, source);
+        self.load_font_family(font_name)
+    }
+    /// A loaded face keeps the file it was read from. Registering it again
+    /// from another one is an error, not a silent reuse of the first file.
+    fn check_font_source(&self, key: &str, source: &FontSource) -> Result<(), ConvertErrorKind> {
+        match self.font_faces.get(key) {
+            Some(loaded) if !loaded.same(source) => Err(ConvertErrorKind::FontConflict {
+                name: key.to_string(),
+                loaded: loaded.to_string(),
+                requested: source.to_string(),
+            }),
+            _ => Ok(()),
+        }
+    }
+    /// Loads the metrics of all registered faces of a font. Each set of face
+    /// sources is added to the shared `font_cache` once. Does nothing until
+    /// the regular face is registered.
+    fn load_font_family(&mut self, font_name: &str) -> Result<(), ConvertErrorKind> {
+        let source = match self.font_faces.get(font_name) {
+            Some(source) => source.clone(),
+            None => return Ok(()),
+        };
+        let bytes = source.read()?;
+        let parsed = parse_font(&source.to_string(), &bytes)?;
+        let mut metrics = vec![(font_name.to_string(), LineMetrics::from_parsed(&parsed))];
+        let mut read_face = |style: FontStyle| match self.font_faces.get(&style.key(font_name)) {
+            Some(source) => {
+                let bytes = source.read()?;
+                metrics.push((style.key(font_name), LineMetrics::from_font(&bytes)));
+                Ok(Some((source.clone(), bytes)))
+            }
+            None => Ok::<_, ConvertErrorKind>(None),
+        };
+        let bold = read_face(FontStyle::Bold)?;
+        let italic = read_face(FontStyle::Italic)?;
+        let bold_italic = read_face(FontStyle::BoldItalic)?;
+        let family_key = [&bold, &italic, &bold_italic]
+            .iter()
+            .map(|face| face.as_ref().map_or(&source, |(s, _)| s).key())
+            .fold(source.key(), |key, face| key + "\n" + &face);
+        let font = match self.font_families.get(&family_key) {
+            Some(font) => *font,
+            None => {
+                let regular = font_data(&source.to_string(), bytes)?;
+                let data = |face: Option<(FontSource, Vec<u8>)>| match face {
+                    Some((source, bytes)) => font_data(&source.to_string(), bytes),
+                    None => Ok(regular.clone()),
+                };
+                let font_family = FontFamily {
+                    bold: data(bold)?,
+                    italic: data(italic)?,
+                    bold_italic: data(bold_italic)?,
+                    regular: regular.clone(),
+                };
+                // all fonts share one cache, a family only measures correctly
+                // against the cache it was added to
+                let font = match self.font_cache.as_mut() {
+                    Some(cache) => cache.add_font_family(font_family),
+                    None => {
+                        let cache = FontCache::new(font_family);
+                        let font = cache.default_font_family();
+                        self.font_cache = Some(cache);
+                        font
+                    }
+                };
+                self.font_families.insert(family_key, font);
+                font
+            }
+        };
+        self.genpdffonts.insert(font_name.to_string(), font);
+        self.parsed_fonts.insert(font_name.to_string(), parsed);
+        self.line_metrics.extend(metrics);
+        Ok(())
+    }
+    /// Adds a font to the PDF document under `key`.
+    fn add_pdf_font(&mut self, key: &str, font: &ParsedFont) -> FontId {
+        let font_id = self.doc.add_font(font).clone();
+        self.font_files.insert(key.to_string(), font_id.clone());
+        font_id
+    }
+    /// `RST`: puts the text state back to what a new report starts with.
+    /// The font and its size are kept; every report sets its own. A page
+    /// size change for a page the previous report did not have is dropped.
+    fn reset_text_state(&mut self) -> Result<(), ConvertErrorKind> {
+        self.next_page_size = None;
+        if self.char_spacing != 0.0 || self.horizontal_scaling != 100.0 {
+            self.char_spacing = 0.0;
+            self.horizontal_scaling = 100.0;
+            self.push_text_spacing();
+        }
+        self.word_spacing = 0.0;
+        self.text_decoration = TextDecoration::None;
+        self.leading = None;
+        self.font_fallbacks.clear();
+        if self.font_style != FontStyle::Regular {
+            self.font_style = FontStyle::Regular;
+            if !self.font_name.is_empty() {
+                self.select_font_face()?;
+            }
+        }
+        Ok(())
+    }
+    /// Makes the `font_style` face of `font_name` current.
+    fn select_font_face(&mut self) -> Result<(), ConvertErrorKind> {
+        self.font_id = self.face_id(&self.font_name.clone())?;
+        Ok(())
+    }
+    /// The `font_style` face of a loaded font, added to the PDF on first use.
+    /// A family without that face uses its regular face.
+    fn face_id(&mut self, font_name: &str) -> Result<FontId, ConvertErrorKind> {
+        let regular = self
+            .font_files
+            .get(font_name)
+            .ok_or_else(|| ConvertErrorKind::UnknownFont(font_name.to_string()))?
+            .clone();
+        if self.font_style == FontStyle::Regular {
+            return Ok(regular);
+        }
+        let key = self.font_style.key(font_name);
+        if let Some(font_id) = self.font_files.get(&key) {
+            return Ok(font_id.clone());
+        }
+        let source = match self.font_faces.get(&key) {
+            Some(source) => source.clone(),
+            None => {
+                self.font_files.insert(key, regular.clone());
+                return Ok(regular);
+            }
+        };
+        let font = parse_font(&source.to_string(), &source.read()?)?;
+        Ok(self.add_pdf_font(&key, &font))
+    }
+    /// `FP` and `FM`: registers the face and, for the regular face, adds the
+    /// font to the PDF and makes it current.
+    fn convert_font_face(
+        &mut self,
+        name: String,
+        style: FontStyle,
+        source: FontSource,
+    ) -> Result<(), ConvertErrorKind> {
+        self.register_font_face(&name, style, source)?;
+        if style != FontStyle::Regular {
+            // drop a fallback to the regular face chosen before
+            self.font_files.remove(&style.key(&name));
+            if self.font_name == name {
+                self.select_font_face()?;
+            }
+            return Ok(());
+        }
+        if !self.font_files.contains_key(&name) {
+            let font = self
+                .parsed_fonts
+                .get(&name)
+                .ok_or_else(|| ConvertErrorKind::UnknownFont(name.clone()))?
+                .clone();
+            self.add_pdf_font(&name, &font);
+        }
+        self.font_name = name;
+        self.select_font_face()
+    }
+    fn has_glyph(&self, font_name: &str, c: char) -> bool {
+        self.parsed_fonts
+            .get(font_name)
+            .is_some_and(|f| f.lookup_glyph_index(c as u32).is_some())
+    }
+    /// The current font or one of its fallbacks has a glyph for `c`.
+    fn can_draw(&self, c: char) -> bool {
+        self.font_runs(&c.to_string())
+            .iter()
+            .any(|(font_name, _)| self.has_glyph(font_name, c))
+    }
+    /// Splits `text` into runs of the current font and, for characters it
+    /// has no glyph for, the first font of its fallback chain that has.
+    /// Whitespace stays in the run before it.
+    fn font_runs(&self, text: &str) -> Vec<(String, String)> {
+        let chain: Vec<&String> = std::iter::once(&self.font_name)
+            .chain(
+                self.font_fallbacks
+                    .get(&self.font_name)
+                    .into_iter()
+                    .flatten(),
             )
+            .collect();
+        let mut runs: Vec<(String, String)> = Vec::new();
+        for c in text.chars() {
+            let font = match runs.last() {
+                Some((font, _)) if c.is_whitespace() => font.clone(),
+                _ => chain
+                    .iter()
+                    .find(|f| self.has_glyph(f, c))
+                    .unwrap_or(&chain[0])
+                    .to_string(),
+            };
+            match runs.last_mut() {
+                Some((f, run)) if *f == font => run.push(c),
+                _ => runs.push((font, c.to_string())),
+            }
+        }
+        runs
+    }
+    pub fn getWidthOfString(&self, text: String) -> Result<f64, ConvertErrorKind> {
+        self.string_width(&text)
+    }
+    /// Draws `text` with its left end at `x`. `x` and `y` are mm from the top
+    /// left of the page, `y` being the baseline.
+    fn draw_text(&mut self, x: f64, y: f64, text: String) -> Result<(), ConvertErrorKind> {
+        self.draw_text_at(x, y, 0.0, text)
+    }
+    /// Draws `text` from `x`, `y` along a baseline turned `angle` degrees
+    /// counter-clockwise.
+    fn draw_text_at(
+        &mut self,
+        x: f64,
+        y: f64,
+        angle: f64,
+        text: String,
+    ) -> Result<(), ConvertErrorKind> {
+        if self.text_decoration != TextDecoration::None {
+            let width = self.string_width(&text)?;
+            self.draw_decoration(x, y, angle, width);
+        }
+        if !self.font_fallbacks.contains_key(&self.font_name) && self.word_spacing == 0.0 {
+            self.draw_run(x, y, angle, text, self.font_id.clone());
+            return Ok(());
+        }
+        let (sin, cos) = angle.to_radians().sin_cos();
+        let (mut x, mut y) = (x, y);
+        for (font_name, run) in self.font_runs(&text) {
+            let font_id = self.face_id(&font_name)?;
+            // PDF word spacing only applies to single byte codes, not to
+            // the two byte codes of embedded fonts, so words are placed here
+            let segments: Vec<&str> = if self.word_spacing != 0.0 {
+                run.split_inclusive(' ').collect()
+            } else {
+                vec![&run]
+            };
+            for segment in segments {
+                let width = self.run_advance(&font_name, segment)?;
+                self.draw_run(x, y, angle, segment.to_string(), font_id.clone());
+                x += width * cos;
+                y -= width * sin;
+            }
+        }
+        Ok(())
+    }
+    fn draw_run(&mut self, x: f64, y: f64, angle: f64, text: String, font: FontId) {
+        let y = self.page_height as f64 - y;
+        self.opttext.push(Op::StartTextSection);
+        if angle == 0.0 {
+            self.opttext.push(Op::SetTextCursor {
+                pos: Point::new(Mm(x as f32), Mm(y as f32)),
+            });
+        } else {
+            let (sin, cos) = angle.to_radians().sin_cos();
+            self.opttext.push(Op::SetTextMatrix {
+                matrix: TextMatrix::Raw([
+                    cos as f32,
+                    sin as f32,
+                    -sin as f32,
+                    cos as f32,
+                    Length::Mm(x).pt() as f32,
+                    Length::Mm(y).pt() as f32,
+                ]),
+            });
+        }
+        if self.char_spacing != 0.0 || self.horizontal_scaling != 100.0 {
+            // a new page starts with the default text state
+            self.push_text_spacing();
+        }
+        self.opttext.push(Op::WriteText {
+            text,
+            font,
+            size: Pt(self.font_size as f32),
+        });
+        self.opttext.push(Op::EndTextSection);
+    }
+    /// Draws the `text_decoration` lines of text `width` long from `x`, `y`
+    /// along a baseline turned `angle` degrees counter-clockwise.
+    fn draw_decoration(&mut self, x: f64, y: f64, angle: f64, width: f64) {
+        let metrics = self
+            .line_metrics
+            .get(&self.font_style.key(&self.font_name))
+            .or_else(|| self.line_metrics.get(&self.font_name))
+            .copied()
+            .unwrap_or_default();
+        let em = Length::Pt(self.font_size as f64).mm();
+        // middle of each line below the baseline in mm
+        let underline = metrics.underline_offset() * em;
+        let underline_thickness = metrics.underline_thickness * em;
+        let lines = match self.text_decoration {
+            TextDecoration::None => vec![],
+            TextDecoration::Underline => vec![(underline, underline_thickness)],
+            TextDecoration::DoubleUnderline => vec![
+                (underline, underline_thickness),
+                (underline + underline_thickness * 2.0, underline_thickness),
+            ],
+            TextDecoration::Strikethrough => vec![(
+                metrics.strikeout_offset() * em,
+                metrics.strikeout_thickness * em,
+            )],
+        };
+        let (sin, cos) = angle.to_radians().sin_cos();
+        // along and below the baseline, y pointing down the page
+        let (dx, dy) = (cos, -sin);
+        let (nx, ny) = (sin, cos);
+        for (offset, thickness) in lines {
+            let top = offset - thickness / 2.0;
+            let corners = [
+                (0.0, top),
+                (width, top),
+                (width, top + thickness),
+                (0.0, top + thickness),
+            ];
+            let ring = corners
+                .iter()
+                .map(|(along, below)| {
+                    let px = x + dx * along + nx * below;
+                    let py = y + dy * along + ny * below;
+                    let point = Point::new(Mm(px as f32), Mm(self.page_height - py as f32));
+                    (point, false)
+                })
+                .collect();
+            self.opttext.push(Op::DrawPolygon {
+                polygon: Polygon {
+                    rings: vec![ring],
+                    mode: PaintMode::Fill,
+                    winding_order: WindingOrder::NonZero,
+                },
+            });
+        }
+    }
+    fn push_text_spacing(&mut self) {
+        self.opttext.push(Op::SetCharacterSpacing {
+            multiplier: self.char_spacing as f32,
+        });
+        self.opttext.push(Op::SetHorizontalScaling {
+            percent: self.horizontal_scaling as f32,
+        });
+    }
+    /// Splits vertical text into pieces: upright characters one em high,
+    /// characters like `ー` turned in their one em cell and runs of other
+    /// text turned on their side, with their length in mm.
+    fn vertical_pieces(&self, text: &str) -> Result<Vec<(bool, String, f64)>, ConvertErrorKind> {
+        let em = Length::Pt(self.font_size as f64).mm();
+        let mut pieces = Vec::new();
+        let mut sideways = String::new();
+        for c in text.chars() {
+            let turned = text::turned_in_vertical(c);
+            let form = text::vertical_form(c).filter(|f| self.can_draw(*f));
+            if !turned && !text::is_cjk(c) && form.is_none() {
+                sideways.push(c);
+                continue;
+            }
+            if !sideways.is_empty() {
+                let width = self.string_width(&sideways)?;
+                pieces.push((false, std::mem::take(&mut sideways), width));
+            }
+            pieces.push((!turned, form.unwrap_or(c).to_string(), em));
+        }
+        if !sideways.is_empty() {
+            let width = self.string_width(&sideways)?;
+            pieces.push((false, sideways, width));
+        }
+        Ok(pieces)
+    }
+    fn draw_text_vertical(&mut self, x: f64, y: f64, text: &str) -> Result<(), ConvertErrorKind> {
+        let em = Length::Pt(self.font_size as f64).mm();
+        let pieces = self.vertical_pieces(text)?;
+        self.without_decoration(|context| {
+            let mut top = y;
+            for (upright, piece, length) in pieces {
+                if upright {
+                    let width = context.string_width(&piece)?;
+                    context.draw_text_at(x - width / 2.0, top + em * text::ASCENT, 0.0, piece)?;
+                } else {
+                    // turned clockwise, the glyphs extend to the right of the
+                    // baseline; a turned character is centred in its cell
+                    let baseline = x - em * (text::ASCENT - 0.5);
+                    let width = context.string_width(&piece)?;
+                    context.draw_text_at(baseline, top + (length - width) / 2.0, -90.0, piece)?;
+                }
+                top += length;
+            }
+            Ok(())
+        })
+    }
+    /// Runs `draw` with `text_decoration` off, restoring it afterwards.
+    fn without_decoration(
+        &mut self,
+        draw: impl FnOnce(&mut Self) -> Result<(), ConvertErrorKind>,
+    ) -> Result<(), ConvertErrorKind> {
+        let decoration = std::mem::take(&mut self.text_decoration);
+        let result = draw(self);
+        self.text_decoration = decoration;
+        result
+    }
+    /// Text and font size with which `text` fits `width`. Uses the current
+    /// font size as the largest one.
+    fn fit_text(
+        &mut self,
+        width: f64,
+        text: &str,
+        fit: TextFit,
+    ) -> Result<(String, i32), ConvertErrorKind> {
+        let size = self.font_size;
+        let result = self.fit_text_sized(width, text, fit);
+        self.font_size = size;
+        result
+    }
+    fn fit_text_sized(
+        &mut self,
+        width: f64,
+        text: &str,
+        fit: TextFit,
+    ) -> Result<(String, i32), ConvertErrorKind> {
+        if let TextFit::Shrink { min_size } = fit {
+            while self.string_width(text)? > width && self.font_size > min_size.max(1) {
+                self.font_size -= 1;
+            }
+        }
+        if self.string_width(text)? <= width {
+            return Ok((text.to_string(), self.font_size));
+        }
+        let ellipsis = if self.can_draw('…') { "…" } else { "..." };
+        let chars: Vec<char> = text.chars().collect();
+        // longest prefix that fits with the ellipsis
+        let (mut fits, mut too_long) = (0, chars.len());
+        while too_long - fits > 1 {
+            let mid = (fits + too_long) / 2;
+            let candidate: String = chars[..mid].iter().collect();
+            if self.string_width(&(candidate.trim_end().to_string() + ellipsis))? <= width {
+                fits = mid;
+            } else {
+                too_long = mid;
+            }
+        }
+        let prefix: String = chars[..fits].iter().collect();
+        Ok((prefix.trim_end().to_string() + ellipsis, self.font_size))
+    }
+    /// Draws the words of `text` spread from `x1` to `x2`.
+    fn draw_text_justified(
+        &mut self,
+        x1: f64,
+        y: f64,
+        x2: f64,
+        text: &str,
+    ) -> Result<(), ConvertErrorKind> {
+        let words: Vec<&str> = text.split_whitespace().collect();
+        if words.len() < 2 {
+            return self.draw_text(x1, y, text.trim().to_string());
+        }
+        let mut widths = Vec::new();
+        for word in words.iter() {
+            widths.push(self.string_width(word)?);
+        }
+        let gap = (x2 - x1 - widths.iter().sum::<f64>()) / (words.len() - 1) as f64;
+        // one line under the whole span rather than under each word
+        self.draw_decoration(x1, y, 0.0, x2 - x1);
+        self.without_decoration(|context| {
+            let mut x = x1;
+            for (word, width) in words.iter().zip(widths) {
+                context.draw_text(x, y, word.to_string())?;
+                x += width + gap;
+            }
+            Ok(())
+        })
+    }
+    /// Width in mm of `text` in the current font and size, measuring each
+    /// fallback run with its own font. Available during `exec` once
+    /// `set_font_dir_and_name` has loaded the fonts.
+    ///
+    /// Includes character and word spacing and horizontal scaling, except
+    /// the character spacing after the last character.
+    pub fn string_width(&self, text: &str) -> Result<f64, ConvertErrorKind> {
+        let mut width = 0.0;
+        for (font_name, run) in self.font_runs(text) {
+            width += self.run_advance(&font_name, &run)?;
+        }
+        if !text.is_empty() {
+            width -= Length::Pt(self.char_spacing).mm() * self.horizontal_scaling / 100.0;
+        }
+        Ok(width)
+    }
+    /// Distance the text position moves when drawing `text` in a font.
+    fn run_advance(&self, font_name: &str, text: &str) -> Result<f64, ConvertErrorKind> {
+        let glyphs = self.run_width(font_name, text)?;
+        let chars = text.chars().count() as f64;
+        let spaces = text.chars().filter(|c| *c == ' ').count() as f64;
+        let spacing = Length::Pt(self.char_spacing * chars + self.word_spacing * spaces).mm();
+        Ok((glyphs + spacing) * self.horizontal_scaling / 100.0)
+    }
+    fn run_width(&self, font_name: &str, text: &str) -> Result<f64, ConvertErrorKind> {
+        let font = self
+            .genpdffonts
+            .get(font_name)
+            .ok_or_else(|| ConvertErrorKind::UnknownFont(font_name.to_string()))?;
+        let font_cache = self
+            .font_cache
+            .as_ref()
+            .ok_or_else(|| ConvertErrorKind::UnknownFont(font_name.to_string()))?;
+        let width: genpdfrev::Mm = self
+            .font_style
+            .face(font)
+            .str_width(font_cache, text, self.font_size as u8)
             .into();
         let f64_width: f64 = width.into();
-        return f64_width;
-    }
-    pub fn convert(&mut self) {
-        let buffer_lines: Vec<String> = self.buffer.clone();
-        for line in buffer_lines.iter() {
-            let v: Vec<&str> = line.split("\t").collect();
-            match v[0].trim() {
-                "P" => {
-                    let ps = match v[1] {
-                        "A4" => PageSize::A4,
-                        "Letter" => PageSize::Letter,
-                        _ => PageSize::A4,
-                    };
-                    let pt = match v[2].trim() {
-                        "Portrait" => PageOrientation::Portrait,
-                        "Landscape" => PageOrientation::Landscape,
-                        _ => PageOrientation::Portrait,
-                    };
-                    let mut width = 0.0;
-                    let mut height = 0.0;
-                    if ps == PageSize::A4 && pt == PageOrientation::Portrait {
-                        width = 210.0;
-                        height = 297.0;
-                    }
-                    if ps == PageSize::A4 && pt == PageOrientation::Landscape {
-                        width = 297.0;
-                        height = 210.0;
-                    }
-                    if ps == PageSize::Letter && pt == PageOrientation::Portrait {
-                        width = 216.0;
-                        height = 279.0;
-                    }
-                    if ps == PageSize::Letter && pt == PageOrientation::Landscape {
-                        width = 279.0;
-                        height = 216.0;
-                    }
-                    self.create_doc(width, height)
-                }
-                "PM" => {
-                    let h = v[1].parse::<f32>().unwrap();
-                    let w = v[2].parse::<f32>().unwrap();
-                    self.create_doc(w, h)
-                }
-                "FF" => {
-                    let fontName = v[1];
-                    let fontDir = v[2].trim();
-                    let font_family = genpdfrev::fonts::from_files(fontDir, fontName, None)
-                        .expect("Failed to load font family");
-                    let mut doc2 = genpdfrev::Document::new(font_family.clone());
-                    let mut fontcache = doc2.font_cache().clone();
-                    let font = fontcache.default_font_family();
-                    self.genpdffonts.insert(fontName.to_string(), font);
-                    self.font_cache = Some(fontcache.clone());
-                    use std::fs;
-
-                    let font_path = format!("{}/{}-Regular.ttf", fontDir, fontName);
-                    let TTF = fs::read(font_path).expect("Failed to read font file");
-                    let font = ParsedFont::from_bytes(&*TTF, 0).unwrap();
-                    let font_id = self.doc.add_font(&font).clone();
-                    self.font_files
-                        .insert(fontName.to_string(), font_id.clone());
-                    self.font_id = font_id.clone();
-                    self.font_name = fontName.to_string();
-                }
-                "F" => {
-                    let fontName = v[1].trim();
-                    self.font_name = fontName.to_string();
-                    self.font_id = self.font_files.get(fontName).unwrap().clone();
-                }
-                "FS" => {
-                    let font_size = v[1].trim().parse::<i32>().unwrap();
-                    self.font_size = font_size;
-                }
-                "TC" => {
-                    let r = v[1].parse::<f32>().unwrap();
-                    let g = v[2].parse::<f32>().unwrap();
-                    let b = v[3].trim().parse::<f32>().unwrap();
-                    self.opttext.push(Op::SetFillColor {
-                        col: Color::Rgb(Rgb::new(r, g, b, None)),
-                    });
-                    self.optgraphic.push(Op::SetFillColor {
-                        col: Color::Rgb(Rgb::new(r, g, b, None)),
-                    });
-                }
-                "TG" => {
-                    let g = v[1].trim().parse::<u8>().unwrap();
-                    let gs = g as f32 / 100.0;
-                    self.opttext.push(Op::SetFillColor {
-                        col: Color::Rgb(Rgb::new(gs, gs, gs, None)),
-                    });
-                    self.optgraphic.push(Op::SetFillColor {
-                        col: Color::Rgb(Rgb::new(gs, gs, gs, None)),
-                    });
-                }
-                "OC" => {
-                    let r = v[1].parse::<f32>().unwrap();
-                    let g = v[2].parse::<f32>().unwrap();
-                    let b = v[3].trim().parse::<f32>().unwrap();
-                    self.opttext.push(Op::SetOutlineColor {
-                        col: Color::Rgb(Rgb::new(r, g, b, None)),
-                    });
-                    self.optgraphic.push(Op::SetOutlineColor {
-                        col: Color::Rgb(Rgb::new(r, g, b, None)),
-                    });
-                }
-                "OG" => {
-                    let g = v[1].trim().parse::<u8>().unwrap();
-                    let gs = g as f32 / 100.0;
-                    self.opttext.push(Op::SetOutlineColor {
-                        col: Color::Rgb(Rgb::new(gs, gs, gs, None)),
-                    });
-                    self.optgraphic.push(Op::SetOutlineColor {
-                        col: Color::Rgb(Rgb::new(gs, gs, gs, None)),
-                    });
-                }
-                "OS" => {
-                    let g = v[1].trim().parse::<f32>().unwrap();
-                    self.optgraphic
-                        .push(Op::SetOutlineThickness { pt: Pt(g as f32) });
-                    self.opttext
-                        .push(Op::SetOutlineThickness { pt: Pt(g as f32) });
-                }
-                "FC" => {
-                    let r = v[1].parse::<u8>().unwrap();
-                    let g = v[2].parse::<u8>().unwrap();
-                    let b = v[3].trim().parse::<u8>().unwrap();
-                    self.optgraphic.push(Op::SetFillColor {
-                        col: Color::Rgb(Rgb::new(r as f32, g as f32, b as f32, None)),
-                    });
-                }
-                "FG" => {
-                    let g = v[1].trim().parse::<u8>().unwrap();
-                    let gs = g as f32 / 100.0;
-                    self.optgraphic.push(Op::SetFillColor {
-                        col: Color::Rgb(Rgb::new(gs, gs, gs, None)),
-                    });
-                }
-                "TL" => {
-                    let x = v[1].parse::<f32>().unwrap();
-                    let y = self.page_height - v[2].parse::<f32>().unwrap();
-                    let text = v[3].trim();
-                    self.opttext.push(Op::StartTextSection);
-                    self.opttext.push(Op::SetTextCursor {
-                        pos: Point::new(Mm(x), Mm(y)),
-                    });
-                    self.opttext.push(Op::WriteText {
-                        text: text.to_string(),
-                        font: self.font_id.clone(),
-                        size: Pt(self.font_size as f32),
-                    });
-                    self.opttext.push(Op::EndTextSection);
-                }
-                "TR" => {
-                    let x = v[1].parse::<f32>().unwrap();
-                    let y = self.page_height - v[2].parse::<f32>().unwrap();
-                    let text = v[3].trim();
-                    // let width: genpdfrev::Mm = self
-                    //     .genpdffonts
-                    //     .get(&self.font_name)
-                    //     .unwrap()
-                    //     .regular
-                    //     .str_width(
-                    //         &self.font_cache.clone().unwrap(),
-                    //         text,
-                    //         self.font_size as u8,
-                    //     )
-                    //     .into();
-                    // let f64_width: f64 = width.into();
-                    let f64_width = self.getWidthOfString(text.to_string());
-                    let modx: f32 = (x - f64_width as f32);
-                    let modx_pt = Pt(modx as f32 * 72.0 / 25.4);
-                    let y_pt = Pt(y as f32 * 72.0 / 25.4);
-                    let xmm = Mm::from(modx_pt);
-                    let ymm = Mm::from(y_pt);
-                    self.opttext.push(Op::StartTextSection);
-                    self.opttext.push(Op::SetTextCursor {
-                        pos: Point::new(xmm, ymm),
-                    });
-                    self.opttext.push(Op::WriteText {
-                        text: text.to_string(),
-                        font: self.font_id.clone(),
-                        size: Pt(self.font_size as f32),
-                    });
-                    self.opttext.push(Op::EndTextSection);
-                }
-                "DP" => {
-                    let dash1 = v[1].parse::<i32>().unwrap();
-                    let gap1 = v[2].parse::<i32>().unwrap();
-                    let dash2 = v[3].parse::<i32>().unwrap();
-                    let gap2 = v[4].trim().parse::<i32>().unwrap();
-                    let dash_pattern = LineDashPattern {
-                        dash_1: Some(dash1 as i64),
-                        gap_1: Some(gap1 as i64),
-                        dash_2: Some(dash2 as i64),
-                        gap_2: Some(gap2 as i64),
-                        ..Default::default()
-                    };
-                    self.optgraphic
-                        .push(Op::SetLineDashPattern { dash: dash_pattern });
-                }
-                "RDP" => {
-                    let dash_pattern = LineDashPattern {
-                        dash_1: None,
-                        gap_1: None,
-                        dash_2: None,
-                        gap_2: None,
-                        ..Default::default()
-                    };
-                    self.optgraphic
-                        .push(Op::SetLineDashPattern { dash: dash_pattern });
-                }
-                "LH" => {
-                    let x1 = v[1].parse::<f32>().unwrap();
-                    let y1 = self.page_height - v[2].parse::<f32>().unwrap();
-                    let x2 = v[3].trim().parse::<f32>().unwrap();
-                    let line1 = Line {
-                        points: vec![
-                            (Point::new(Mm(x1), Mm(y1)), false),
-                            (Point::new(Mm(x2), Mm(y1)), false),
-                        ],
-                        is_closed: true,
-                    };
-                    self.optgraphic.push(Op::DrawLine { line: line1 });
-                }
-                "LHS" => {
-                    let x1 = v[1].parse::<f32>().unwrap();
-                    let y1 = self.page_height - v[2].parse::<f32>().unwrap();
-                    let s: String = v[3].to_string();
-                    let o1 = v[4].trim().parse::<f32>().unwrap();
-                    let o2 = v[5].trim().parse::<f32>().unwrap();
-                    let width = self.getWidthOfString(s.clone());
-                    let line1 = Line {
-                        points: vec![
-                            (Point::new(Mm(x1 + o1), Mm(y1)), false),
-                            (Point::new(Mm(x1 + width as f32 + o2), Mm(y1)), false),
-                        ],
-                        is_closed: true,
-                    };
-                    self.optgraphic.push(Op::DrawLine { line: line1 });
+        Ok(f64_width)
+    }
+    /// Converts the buffer into PDF pages.
+    ///
+    /// Stops at the first line that cannot be converted and returns its line
+    /// number, opcode and cause.
+    pub fn convert(&mut self) -> Result<(), ConvertError> {
+        let buffer_lines = std::mem::take(&mut self.buffer);
+        let result = self.convert_lines(&buffer_lines);
+        self.buffer = buffer_lines;
+        self.record_convert_error(result)?;
+        self.new_page_draw();
+        Ok(())
+    }
+    /// Keeps the first convert error for `save` and `get_pdf_bytes`.
+    fn record_convert_error(
+        &mut self,
+        result: Result<(), ConvertError>,
+    ) -> Result<(), ConvertError> {
+        if let Err(e) = &result {
+            self.convert_error.get_or_insert_with(|| e.clone());
+        }
+        result
+    }
+    fn convert_lines(&mut self, lines: &[String]) -> Result<(), ConvertError> {
+        for (i, line) in lines.iter().enumerate() {
+            self.convert_line(i + 1, line)?;
+        }
+        Ok(())
+    }
+    /// Converts buffer commands read from `reader` into PDF pages.
+    ///
+    /// Unlike `read_buffer` + `convert`, the buffer is read line by line and
+    /// never held in memory. The pages are, until `save`: `printpdf` writes
+    /// the whole document at once, so memory grows with the number of pages.
+    /// Use `convert_reader_batches` to keep it bounded. Images and layers are
+    /// shared between pages, so a page adds only its drawing operations.
+    /// Format 1 buffers are upgraded line by line. Line numbers in errors
+    /// are 1-based in the input. Each call starts from the default text
+    /// state, as after `RST`, so several buffers can be converted into one
+    /// document.
+    pub fn convert_reader<R: BufRead>(&mut self, reader: R) -> Result<(), ConvertError> {
+        let result = self.convert_lines_from(reader, |_| Ok(()));
+        self.record_convert_error(result)?;
+        self.new_page_draw();
+        Ok(())
+    }
+    /// Converts buffer commands read from `reader` like `convert_reader`, but
+    /// hands the pages to `write` as PDFs of `batch_pages` pages each (the
+    /// last one may be shorter) as soon as they are finished. Only one batch
+    /// of pages is held in memory, `batch_pages` of 1 keeps a single page.
+    ///
+    /// `printpdf` cannot append pages to a PDF it has written, so a report
+    /// larger than one batch becomes several PDFs, each with the fonts and
+    /// images it uses. The pages are not kept, `save` afterwards writes an
+    /// empty document.
+    pub fn convert_reader_batches<R, W>(
+        &mut self,
+        reader: R,
+        batch_pages: usize,
+        mut write: W,
+    ) -> Result<(), ConvertError>
+    where
+        R: BufRead,
+        W: FnMut(Vec<u8>) -> std::io::Result<()>,
+    {
+        let batch_pages = batch_pages.max(1);
+        let options = self.save_options();
+        let mut write_batch = |context: &mut Context| -> Result<(), ConvertError> {
+            let pdf_bytes = context.pdf_bytes(&options)?;
+            context.pages.clear();
+            write(pdf_bytes).map_err(|e| save_error(e.to_string()))
+        };
+        let mut result = self.convert_lines_from(reader, |context| {
+            if context.pages.len() >= batch_pages {
+                write_batch(context)?;
+            }
+            Ok(())
+        });
+        if result.is_ok() {
+            self.new_page_draw();
+            result = write_batch(self);
+        }
+        self.record_convert_error(result)
+    }
+    /// Converts the lines of `reader`, calling `line_done` after each one.
+    fn convert_lines_from<R: BufRead>(
+        &mut self,
+        mut reader: R,
+        mut line_done: impl FnMut(&mut Context) -> Result<(), ConvertError>,
+    ) -> Result<(), ConvertError> {
+        self.reset_text_state().map_err(|kind| ConvertError {
+            line: 0,
+            opcode: "RST".to_string(),
+            kind,
+        })?;
+        let mut line = String::new();
+        let mut line_no = 0;
+        let mut format = 1;
+        loop {
+            line.clear();
+            line_no += 1;
+            let read = reader.read_line(&mut line).map_err(|e| ConvertError {
+                line: line_no,
+                opcode: String::new(),
+                kind: ConvertErrorKind::Buffer(BufferError::Io(e)),
+            })?;
+            if read == 0 {
+                break;
+            }
+            if line_no == 1 {
+                format = command::buffer_format(&line).map_err(|e| ConvertError {
+                    line: line_no,
+                    opcode: "VER".to_string(),
+                    kind: ConvertErrorKind::Buffer(e),
+                })?;
+            }
+            if format == 1 {
+                for upgraded in command::upgrade_v1_line(&line) {
+                    self.convert_line(line_no, &upgraded)?;
                 }
-                "LHSR" => {
-                    let x1 = v[1].parse::<f32>().unwrap();
-                    let y1 = self.page_height - v[2].parse::<f32>().unwrap();
-                    let s: String = v[3].to_string();
-                    let o1 = v[4].trim().parse::<f32>().unwrap();
-                    let o2 = v[5].trim().parse::<f32>().unwrap();
-                    let width = self.getWidthOfString(s.clone());
-                    let line1 = Line {
-                        points: vec![
-                            (Point::new(Mm(x1 - width as f32 + o1), Mm(y1)), false),
-                            (Point::new(Mm(x1 + o2), Mm(y1)), false),
-                        ],
-                        is_closed: true,
-                    };
-                    self.optgraphic.push(Op::DrawLine { line: line1 });
+            } else {
+                self.convert_line(line_no, &line)?;
+            }
+            line_done(self)?;
+        }
+        Ok(())
+    }
+    /// Converts one buffer line. `line_no` is only used for errors.
+    fn convert_line(&mut self, line_no: usize, line: &str) -> Result<(), ConvertError> {
+        let mut command = match BufferCommand::parse(line) {
+            Ok(command) => command,
+            Err(ParseError::Empty) => return Ok(()),
+            Err(e) => {
+                return Err(ConvertError {
+                    line: line_no,
+                    opcode: line.split('\t').next().unwrap_or("").trim().to_string(),
+                    kind: ConvertErrorKind::Parse(e),
+                })
+            }
+        };
+        if let Some(text) = command.text_mut() {
+            *text = command::unescape_markers(text);
+        }
+        let opcode = command.opcode();
+        self.convert_command(command).map_err(|kind| ConvertError {
+            line: line_no,
+            opcode: opcode.to_string(),
+            kind,
+        })
+    }
+    fn convert_command(&mut self, command: BufferCommand) -> Result<(), ConvertErrorKind> {
+        match command {
+            BufferCommand::Page { size, orientation } => {
+                let (width, height) = size.dimensions(&orientation);
+                self.create_doc(width, height)
+            }
+            BufferCommand::PageMm { width, height } => self.create_doc(width, height),
+            BufferCommand::FontFile { name, dir } => {
+                self.load_font_metrics(&dir, &name)?;
+                // added already by an earlier report in the same document
+                if !self.font_files.contains_key(&name) {
+                    let font = self
+                        .parsed_fonts
+                        .get(&name)
+                        .ok_or_else(|| ConvertErrorKind::UnknownFont(name.clone()))?
+                        .clone();
+                    self.add_pdf_font(&name, &font);
                 }
-                "LV" => {
-                    let x1 = v[1].parse::<f32>().unwrap();
-                    let y1 = self.page_height - v[2].parse::<f32>().unwrap();
-                    let y2 = self.page_height - v[3].trim().parse::<f32>().unwrap();
-                    let line1 = Line {
-                        points: vec![
-                            (Point::new(Mm(x1), Mm(y1)), false),
-                            (Point::new(Mm(x1), Mm(y2)), false),
-                        ],
-                        is_closed: true,
-                    };
-                    self.optgraphic.push(Op::DrawLine { line: line1 });
+                self.font_name = name;
+                self.select_font_face()?;
+            }
+            BufferCommand::Font { name } => {
+                if !self.font_files.contains_key(&name) {
+                    return Err(ConvertErrorKind::UnknownFont(name));
                 }
-                "L" => {
-                    let x1 = v[1].parse::<f32>().unwrap();
-                    let y1 = self.page_height - v[2].parse::<f32>().unwrap();
-                    let x2 = v[3].parse::<f32>().unwrap();
-                    let y2 = self.page_height - v[4].trim().parse::<f32>().unwrap();
-                    let line1 = Line {
-                        points: vec![
-                            (Point::new(Mm(x1), Mm(y1)), false),
-                            (Point::new(Mm(x2), Mm(y2)), false),
-                        ],
-                        is_closed: true,
+                self.font_name = name;
+                self.select_font_face()?;
+            }
+            BufferCommand::FontStyle(style) => {
+                self.font_style = style;
+                self.select_font_face()?;
+            }
+            BufferCommand::FontPath { name, style, path } => {
+                self.convert_font_face(name, style, FontSource::File(path))?
+            }
+            BufferCommand::FontMemory { name, style } => {
+                let key = style.key(&name);
+                let bytes = *self
+                    .font_bytes
+                    .get(&key)
+                    .ok_or(ConvertErrorKind::UnknownFont(key))?;
+                self.convert_font_face(name, style, FontSource::Bytes(bytes))?
+            }
+            BufferCommand::FontFallback { name, fallbacks } if fallbacks.is_empty() => {
+                self.font_fallbacks.remove(&name);
+            }
+            BufferCommand::FontFallback { name, fallbacks } => {
+                self.font_fallbacks.insert(name, fallbacks);
+            }
+            BufferCommand::ResetState => self.reset_text_state()?,
+            BufferCommand::FooterStart | BufferCommand::FooterEnd => {}
+            BufferCommand::FontSize(font_size) => {
+                self.font_size = font_size;
+            }
+            BufferCommand::TextColor { r, g, b } => {
+                let (r, g, b) = (r as f32, g as f32, b as f32);
+                self.opttext.push(Op::SetFillColor {
+                    col: Color::Rgb(Rgb::new(r, g, b, None)),
+                });
+                self.optgraphic.push(Op::SetFillColor {
+                    col: Color::Rgb(Rgb::new(r, g, b, None)),
+                });
+            }
+            BufferCommand::TextGreyScale(g) => {
+                let gs = g as f32 / 100.0;
+                self.opttext.push(Op::SetFillColor {
+                    col: Color::Rgb(Rgb::new(gs, gs, gs, None)),
+                });
+                self.optgraphic.push(Op::SetFillColor {
+                    col: Color::Rgb(Rgb::new(gs, gs, gs, None)),
+                });
+            }
+            BufferCommand::OutlineColor { r, g, b } => {
+                let (r, g, b) = (r as f32, g as f32, b as f32);
+                self.opttext.push(Op::SetOutlineColor {
+                    col: Color::Rgb(Rgb::new(r, g, b, None)),
+                });
+                self.optgraphic.push(Op::SetOutlineColor {
+                    col: Color::Rgb(Rgb::new(r, g, b, None)),
+                });
+            }
+            BufferCommand::OutlineGreyScale(g) => {
+                let gs = g as f32 / 100.0;
+                self.opttext.push(Op::SetOutlineColor {
+                    col: Color::Rgb(Rgb::new(gs, gs, gs, None)),
+                });
+                self.optgraphic.push(Op::SetOutlineColor {
+                    col: Color::Rgb(Rgb::new(gs, gs, gs, None)),
+                });
+            }
+            BufferCommand::CharSpacing(spacing) => {
+                self.char_spacing = spacing;
+                self.push_text_spacing();
+            }
+            BufferCommand::HorizontalScaling(percent) => {
+                self.horizontal_scaling = percent;
+                self.push_text_spacing();
+            }
+            BufferCommand::WordSpacing(spacing) => self.word_spacing = spacing,
+            BufferCommand::TextDecoration(decoration) => self.text_decoration = decoration,
+            BufferCommand::OutlineThickness(g) => {
+                self.optgraphic
+                    .push(Op::SetOutlineThickness { pt: Pt(g as f32) });
+                self.opttext
+                    .push(Op::SetOutlineThickness { pt: Pt(g as f32) });
+            }
+            BufferCommand::FillColor { r, g, b } => {
+                self.optgraphic.push(Op::SetFillColor {
+                    col: Color::Rgb(Rgb::new(r as f32, g as f32, b as f32, None)),
+                });
+            }
+            BufferCommand::FillGreyScale(g) => {
+                let gs = g as f32 / 100.0;
+                self.optgraphic.push(Op::SetFillColor {
+                    col: Color::Rgb(Rgb::new(gs, gs, gs, None)),
+                });
+            }
+            BufferCommand::TextLeft { x, y, text } => self.draw_text(x, y, text)?,
+            BufferCommand::TextRight { x, y, text } => {
+                let width = self.string_width(&text)?;
+                self.draw_text(x - width, y, text)?;
+            }
+            BufferCommand::TextCenter { x, y, text } => {
+                let width = self.string_width(&text)?;
+                self.draw_text(x - width / 2.0, y, text)?;
+            }
+            BufferCommand::TextJustify { x1, y1, x2, text } => {
+                self.draw_text_justified(x1, y1, x2, &text)?
+            }
+            BufferCommand::TextFit {
+                x,
+                y,
+                width,
+                align,
+                fit,
+                text,
+            } => {
+                let (text, size) = self.fit_text(width, &text, fit)?;
+                let font_size = std::mem::replace(&mut self.font_size, size);
+                let result = self.string_width(&text).and_then(|w| {
+                    let x = match align {
+                        TextAlign::Right => x + width - w,
+                        TextAlign::Center => x + (width - w) / 2.0,
+                        TextAlign::Left | TextAlign::Justify => x,
                     };
-                    self.optgraphic.push(Op::DrawLine { line: line1 });
-                }
-                "R" => {
-                    let x1 = v[1].parse::<f32>().unwrap();
-                    let y1 = self.page_height - v[2].parse::<f32>().unwrap();
-                    let x2 = v[3].parse::<f32>().unwrap();
-                    let y2 = self.page_height - v[4].parse::<f32>().unwrap();
-                    let fill = v[5].trim();
-                    if fill == "Y" {
-                        //self.optgraphic.push(Op::SetFillColor {col: Color::Rgb(Rgb::new(0.95,0.95,0.95,None))});
-                        self.optgraphic.push(Op::DrawPolygon {
-                            polygon: Polygon {
-                                rings: vec![vec![
-                                    (Point::new(Mm(x1), Mm(y1)), false),
-                                    (Point::new(Mm(x1), Mm(y2)), false),
-                                    (Point::new(Mm(x2), Mm(y2)), false),
-                                    (Point::new(Mm(x2), Mm(y1)), false),
-                                ]],
-                                mode: PaintMode::FillStroke,
-                                winding_order: WindingOrder::NonZero,
-                            },
-                        });
-                    } else {
-                        self.opttext.push(Op::DrawPolygon {
-                            polygon: Polygon {
-                                rings: vec![vec![
-                                    (Point::new(Mm(x1), Mm(y1)), false),
-                                    (Point::new(Mm(x1), Mm(y2)), false),
-                                    (Point::new(Mm(x2), Mm(y2)), false),
-                                    (Point::new(Mm(x2), Mm(y1)), false),
-                                ]],
-                                mode: PaintMode::Stroke,
-                                winding_order: WindingOrder::NonZero,
-                            },
-                        });
+                    self.draw_text(x, y, text)
+                });
+                self.font_size = font_size;
+                result?
+            }
+            BufferCommand::TextRotated { x, y, angle, text } => {
+                self.draw_text_at(x, y, angle, text)?
+            }
+            BufferCommand::TextVertical { x, y, text } => self.draw_text_vertical(x, y, &text)?,
+            BufferCommand::TextBox {
+                x,
+                y,
+                width,
+                line_height,
+                align,
+                text,
+            } => {
+                let lines = text::wrap_text(&text, width, &mut |s| self.string_width(s))?;
+                for (i, line) in lines.into_iter().enumerate() {
+                    let y = y + i as f64 * line_height;
+                    match align {
+                        TextAlign::Left => self.draw_text(x, y, line.text)?,
+                        TextAlign::Right => {
+                            let w = self.string_width(&line.text)?;
+                            self.draw_text(x + width - w, y, line.text)?
+                        }
+                        TextAlign::Center => {
+                            let w = self.string_width(&line.text)?;
+                            self.draw_text(x + (width - w) / 2.0, y, line.text)?
+                        }
+                        TextAlign::Justify if !line.last => {
+                            self.draw_text_justified(x, y, x + width, &line.text)?
+                        }
+                        TextAlign::Justify => self.draw_text(x, y, line.text)?,
                     }
                 }
-                "I" => {
-                    let x = v[1].parse::<f32>().unwrap();
-                    let y = self.page_height - v[2].parse::<f32>().unwrap();
-                    let w = v[3].parse::<f32>().unwrap();
-                    let h = v[4].parse::<f32>().unwrap();
-                    let xpt = Pt(x * 72.0 / 25.4);
-                    let ypt = Pt(y * 72.0 / 25.4);
-                    let wpt = Pt(w * 72.0 / 25.4);
-                    let hpt = Pt(h * 72.0 / 25.4);
-                    let wptx: PtTo1i32 = From::from(wpt);
-                    let hptx: PtTo1i32 = From::from(hpt);
-                    let wi32: f32 = wptx.into();
-                    let hi32: f32 = hptx.into();
-                    let img = v[5].trim();
-                    let file_path = img;
-                    let (width, height) = get_image_dimensions(file_path).unwrap();
-                    let simg = format!("{}", img);
-                    let image_bytes = fs::read(simg).expect("Failed to read image file");
-                    let image = RawImage::decode_from_bytes(&image_bytes).unwrap();
-                    let image_xobject_id = self.doc.add_image(&image);
-                    let mut scalex = Some(wi32 / width as f32);
-                    let mut scaleY = Some(hi32 / height as f32);
-                    self.opttext.push(Op::UseXObject {
-                        id: image_xobject_id.clone(),
-                        transform: XObjectTransform {
-                            translate_x: Some(xpt),
-                            translate_y: Some(ypt),
-                            scale_x: scalex.clone(),
-                            scale_y: scaleY.clone(),
-                            dpi: Some(72.0),
-                            ..Default::default()
+            }
+            BufferCommand::DashPattern {
+                dash1,
+                gap1,
+                dash2,
+                gap2,
+            } => {
+                let dash_pattern = LineDashPattern {
+                    dash_1: Some(dash1 as i64),
+                    gap_1: Some(gap1 as i64),
+                    dash_2: Some(dash2 as i64),
+                    gap_2: Some(gap2 as i64),
+                    ..Default::default()
+                };
+                self.optgraphic
+                    .push(Op::SetLineDashPattern { dash: dash_pattern });
+            }
+            BufferCommand::ResetDashPattern => {
+                let dash_pattern = LineDashPattern {
+                    dash_1: None,
+                    gap_1: None,
+                    dash_2: None,
+                    gap_2: None,
+                    ..Default::default()
+                };
+                self.optgraphic
+                    .push(Op::SetLineDashPattern { dash: dash_pattern });
+            }
+            BufferCommand::LineHorizontal { x1, y1, x2 } => {
+                let x1 = x1 as f32;
+                let y1 = self.page_height - y1 as f32;
+                let x2 = x2 as f32;
+                let line1 = Line {
+                    points: vec![
+                        (Point::new(Mm(x1), Mm(y1)), false),
+                        (Point::new(Mm(x2), Mm(y1)), false),
+                    ],
+                    is_closed: true,
+                };
+                self.optgraphic.push(Op::DrawLine { line: line1 });
+            }
+            BufferCommand::LineHorizontalStrlen {
+                x1,
+                y1,
+                text,
+                offset_left,
+                offset_right,
+            } => {
+                let x1 = x1 as f32;
+                let y1 = self.page_height - y1 as f32;
+                let o1 = offset_left as f32;
+                let o2 = offset_right as f32;
+                let width = self.string_width(&text)?;
+                let line1 = Line {
+                    points: vec![
+                        (Point::new(Mm(x1 + o1), Mm(y1)), false),
+                        (Point::new(Mm(x1 + width as f32 + o2), Mm(y1)), false),
+                    ],
+                    is_closed: true,
+                };
+                self.optgraphic.push(Op::DrawLine { line: line1 });
+            }
+            BufferCommand::LineHorizontalRightStrlen {
+                x1,
+                y1,
+                text,
+                offset_left,
+                offset_right,
+            } => {
+                let x1 = x1 as f32;
+                let y1 = self.page_height - y1 as f32;
+                let o1 = offset_left as f32;
+                let o2 = offset_right as f32;
+                let width = self.string_width(&text)?;
+                let line1 = Line {
+                    points: vec![
+                        (Point::new(Mm(x1 - width as f32 + o1), Mm(y1)), false),
+                        (Point::new(Mm(x1 + o2), Mm(y1)), false),
+                    ],
+                    is_closed: true,
+                };
+                self.optgraphic.push(Op::DrawLine { line: line1 });
+            }
+            BufferCommand::LineVertical { x1, y1, y2 } => {
+                let x1 = x1 as f32;
+                let y1 = self.page_height - y1 as f32;
+                let y2 = self.page_height - y2 as f32;
+                let line1 = Line {
+                    points: vec![
+                        (Point::new(Mm(x1), Mm(y1)), false),
+                        (Point::new(Mm(x1), Mm(y2)), false),
+                    ],
+                    is_closed: true,
+                };
+                self.optgraphic.push(Op::DrawLine { line: line1 });
+            }
+            BufferCommand::Line { x1, y1, x2, y2 } => {
+                let x1 = x1 as f32;
+                let y1 = self.page_height - y1 as f32;
+                let x2 = x2 as f32;
+                let y2 = self.page_height - y2 as f32;
+                let line1 = Line {
+                    points: vec![
+                        (Point::new(Mm(x1), Mm(y1)), false),
+                        (Point::new(Mm(x2), Mm(y2)), false),
+                    ],
+                    is_closed: true,
+                };
+                self.optgraphic.push(Op::DrawLine { line: line1 });
+            }
+            BufferCommand::Rect {
+                x1,
+                y1,
+                x2,
+                y2,
+                fill,
+            } => {
+                let x1 = x1 as f32;
+                let y1 = self.page_height - y1 as f32;
+                let x2 = x2 as f32;
+                let y2 = self.page_height - y2 as f32;
+                if fill {
+                    //self.optgraphic.push(Op::SetFillColor {col: Color::Rgb(Rgb::new(0.95,0.95,0.95,None))});
+                    self.optgraphic.push(Op::DrawPolygon {
+                        polygon: Polygon {
+                            rings: vec![vec![
+                                (Point::new(Mm(x1), Mm(y1)), false),
+                                (Point::new(Mm(x1), Mm(y2)), false),
+                                (Point::new(Mm(x2), Mm(y2)), false),
+                                (Point::new(Mm(x2), Mm(y1)), false),
+                            ]],
+                            mode: PaintMode::FillStroke,
+                            winding_order: WindingOrder::NonZero,
+                        },
+                    });
+                } else {
+                    self.opttext.push(Op::DrawPolygon {
+                        polygon: Polygon {
+                            rings: vec![vec![
+                                (Point::new(Mm(x1), Mm(y1)), false),
+                                (Point::new(Mm(x1), Mm(y2)), false),
+                                (Point::new(Mm(x2), Mm(y2)), false),
+                                (Point::new(Mm(x2), Mm(y1)), false),
+                            ]],
+                            mode: PaintMode::Stroke,
+                            winding_order: WindingOrder::NonZero,
                         },
                     });
                 }
-                "NP" => self.new_page_draw(),
-                _ => {
-                    println!("Unknown command: {}", v[0]);
+            }
+            BufferCommand::Image { x, y, w, h, path } => {
+                let x = x as f32;
+                let y = self.page_height - y as f32;
+                let w = w as f32;
+                let h = h as f32;
+                let xpt = Pt(Length::Mm(x as f64).pt() as f32);
+                let ypt = Pt(Length::Mm(y as f64).pt() as f32);
+                let wpt = Pt(Length::Mm(w as f64).pt() as f32);
+                let hpt = Pt(Length::Mm(h as f64).pt() as f32);
+                let wptx: PtTo1i32 = From::from(wpt);
+                let hptx: PtTo1i32 = From::from(hpt);
+                let wi32: f32 = wptx.into();
+                let hi32: f32 = hptx.into();
+                let (image_xobject_id, width, height) = self.image(&path)?;
+                let mut scalex = Some(wi32 / width as f32);
+                let mut scaleY = Some(hi32 / height as f32);
+                self.opttext.push(Op::UseXObject {
+                    id: image_xobject_id.clone(),
+                    transform: XObjectTransform {
+                        translate_x: Some(xpt),
+                        translate_y: Some(ypt),
+                        scale_x: scalex.clone(),
+                        scale_y: scaleY.clone(),
+                        dpi: Some(72.0),
+                        ..Default::default()
+                    },
+                });
+            }
+            BufferCommand::PageChange { size, orientation } => {
+                self.next_page_size = Some(size.dimensions(&orientation));
+            }
+            BufferCommand::NewPage => {
+                self.new_page_draw();
+                if let Some((w, h)) = self.next_page_size.take() {
+                    self.page_width = w;
+                    self.page_height = h;
                 }
             }
+            // TB lines carry their line height, LD only matters when writing
+            BufferCommand::Leading(_)
+            | BufferCommand::PageNumber { .. }
+            | BufferCommand::Version { .. } => {}
         }
-        self.new_page_draw();
+        Ok(())
     }
 }
 struct PtTo1i32(Pt);
@@ -673,17 +2079,79 @@ impl Into<f32> for PtTo1i32 {
     }
 }
 impl Context {
-    pub fn save(&mut self, filename: &str) {
-        let pdf_bytes: Vec<u8> = self
-            .doc
-            .with_pages(self.pages.clone())
-            .save(&Default::default());
-        fs::write(filename, pdf_bytes).expect("Unable to write file");
-    }
-    pub fn get_pdf_bytes(&mut self) -> Vec<u8> {
-        self.doc
-            .with_pages(self.pages.clone())
-            .save(&Default::default())
+    /// The image at `path` in `doc`, added on first use.
+    fn image(&mut self, path: &str) -> Result<(XObjectId, u32, u32), ConvertErrorKind> {
+        if let Some(image) = self.images.get(path) {
+            return Ok(image.clone());
+        }
+        let image_error = |message: String| ConvertErrorKind::Image {
+            path: path.to_string(),
+            message,
+        };
+        let (width, height) = get_image_dimensions(path).map_err(|e| image_error(e.to_string()))?;
+        let image_bytes = fs::read(path).map_err(|e| image_error(e.to_string()))?;
+        let image =
+            RawImage::decode_from_bytes(&image_bytes).map_err(|e| image_error(e.to_string()))?;
+        let image = (self.doc.add_image(&image), width, height);
+        self.images.insert(path.to_string(), image.clone());
+        Ok(image)
+    }
+    /// Writes the PDF to `filename`. Fails with the error of a failed
+    /// `convert`, or with `ConvertErrorKind::Save` if the file cannot be
+    /// written.
+    pub fn save(&mut self, filename: &str) -> Result<(), ConvertError> {
+        let pdf_bytes = self.get_pdf_bytes()?;
+        fs::write(filename, pdf_bytes).map_err(|e| save_error(format!("{}: {}", filename, e)))
+    }
+    /// Writes the PDF to `w`, e.g. an HTTP response or an archive entry.
+    ///
+    /// `options` is passed to `printpdf` as is (compression, image
+    /// optimisation, font subsetting). `printpdf` builds the whole PDF in
+    /// memory, which is then written to `w`; nothing is streamed.
+    pub fn save_to<W: Write>(
+        &mut self,
+        w: &mut W,
+        options: &PdfSaveOptions,
+    ) -> Result<(), ConvertError> {
+        let pdf_bytes = self.pdf_bytes(options)?;
+        w.write_all(&pdf_bytes)
+            .map_err(|e| save_error(e.to_string()))
+    }
+    /// The PDF in memory, or the error of a failed `convert`.
+    pub fn get_pdf_bytes(&mut self) -> Result<Vec<u8>, ConvertError> {
+        let options = self.save_options();
+        self.pdf_bytes(&options)
+    }
+    /// The PDF of `pages`. The pages are lent to the document for the save
+    /// only, so saving again does not add them twice.
+    fn pdf_bytes(&mut self, options: &PdfSaveOptions) -> Result<Vec<u8>, ConvertError> {
+        if let Some(e) = &self.convert_error {
+            return Err(e.clone());
+        }
+        self.doc.pages = std::mem::take(&mut self.pages);
+        let pdf_bytes = self.doc.save(options);
+        self.pages = std::mem::take(&mut self.doc.pages);
+        Ok(pdf_bytes)
+    }
+    /// Whether `save` and `get_pdf_bytes` embed only the glyphs the document
+    /// uses. A full Noto Sans JP face is several MB, a subset of an invoice
+    /// a few KB. Turn it off to keep the fonts editable in the PDF.
+    pub fn set_subset_fonts(&mut self, subset_fonts: bool) {
+        self.subset_fonts = subset_fonts;
+    }
+    /// Options `save` and `get_pdf_bytes` pass to `printpdf`.
+    pub fn save_options(&self) -> PdfSaveOptions {
+        PdfSaveOptions {
+            subset_fonts: self.subset_fonts,
+            ..Default::default()
+        }
+    }
+}
+fn save_error(message: String) -> ConvertError {
+    ConvertError {
+        line: 0,
+        opcode: String::new(),
+        kind: ConvertErrorKind::Save(message),
     }
 }
 fn get_image_dimensions(file_path: &str) -> image::ImageResult<(u32, u32)> {
@@ -693,8 +2161,13 @@ fn get_image_dimensions(file_path: &str) -> image::ImageResult<(u32, u32)> {
 }
 impl Context {
     fn create_doc(&mut self, w: f32, h: f32) {
-        let doc = PdfDocument::new("PDF");
-        self.doc = doc.clone();
+        // A later P (e.g. the start of an appended report) only changes the
+        // page size. Replacing the document would drop its fonts.
+        if self.pages.is_empty() && self.font_files.is_empty() {
+            self.doc = PdfDocument::new("PDF");
+            self.images.clear();
+            self.layers = None;
+        }
         self.page_height = h;
         self.page_width = w;
         self.cur_vpos = 0.0;
@@ -704,8 +2177,17 @@ impl Context {
     fn new_page_draw(&mut self) {
         //context.opttext.push(Op::EndTextSection);
         let mut ops: Vec<Op> = Vec::new();
-        let grapiclayer = self.doc.add_layer(&Layer::new("Grapic content"));
-        let textlayer = self.doc.add_layer(&Layer::new("Text content"));
+        let (grapiclayer, textlayer) = match &self.layers {
+            Some(layers) => layers.clone(),
+            None => {
+                let layers = (
+                    self.doc.add_layer(&Layer::new("Grapic content")),
+                    self.doc.add_layer(&Layer::new("Text content")),
+                );
+                self.layers = Some(layers.clone());
+                layers
+            }
+        };
         ops.push(Op::BeginLayer {
             layer_id: grapiclayer.clone(),
         });
@@ -728,151 +2210,139 @@ impl Context {
     }
 }
 impl Context {
-    pub fn exec(&mut self) {
+    pub fn exec(&mut self) -> Result<(), ReportError> {
+        if let Some(height) = self.footer_height {
+            self.footer_vpos = self.printable_height() - height;
+        }
         if self.footer_vpos == 0.0 {
-            panic!("Footer position is not set");
+            return Err(ReportError::FooterPositionNotSet);
         }
         self.page = 1;
         self.page_total = 1;
         self.page_report = 1;
-        self.buffer.push(
-            "V\tPAGE\t".to_string()
-                + self.page.to_string().as_str()
-                + "\tTOTALPAGE\t"
-                + self.page_total.to_string().as_str()
-                + "\n",
-        );
-        if self.page_header.len() > 0 {
-            // 1. Remove the PageHeader from the vector.
-            let header = self.page_header.remove(0);
-            // 2. Execute the header.
-            header.Execute(self);
-            //3. insert page_header
-            self.page_header.insert(0, header);
-        }
+        self.push_command(BufferCommand::PageNumber {
+            page: self.page,
+            total_page: self.page_total,
+        });
+        self.execute_page_header()?;
 
         for i in self.cur_line as usize..self.input.len() {
             self.cur_line = i as i32;
-            self.exec_detail();
+            self.exec_detail()?;
         }
         if self.report_summary.len() > 0 {
             let report_summary = self.report_summary.remove(0 as usize);
-            let height = report_summary.GetHeight(self);
-            self.page_break_check(height);
-            report_summary.Execute(self);
+            let result = self.execute_report_summary(report_summary.as_ref());
             self.report_summary.insert(0 as usize, report_summary);
-            self.cur_vpos = self.cur_vpos + height as f64;
+            result?;
         }
+        self.execute_footer()?;
+        self.execute_replace_pagetotal();
+        // a change requested on the last page has no page to apply to
+        self.next_page_size = None;
+        Ok(())
+    }
+    fn band_error(&self, band: BandKind, source: exec::BandError) -> ReportError {
+        ReportError::Band {
+            band,
+            cur_line: self.cur_line,
+            source,
+        }
+    }
+    fn execute_page_header(&mut self) -> Result<(), ReportError> {
+        if self.page_header.len() > 0 {
+            // 1. Remove the PageHeader from the vector.
+            let header = self.page_header.remove(0);
+            // 2. Execute the header.
+            let result = header.TryExecute(self);
+            //3. insert page_header
+            self.page_header.insert(0, header);
+            result.map_err(|e| self.band_error(BandKind::PageHeader, e))?;
+        }
+        Ok(())
+    }
+    fn execute_footer(&mut self) -> Result<(), ReportError> {
         if self.footor.len() > 0 {
             let footer = self.footor.remove(0);
-            footer.Execute(self);
+            self.push_command(BufferCommand::FooterStart);
+            let result = footer.TryExecute(self);
+            self.push_command(BufferCommand::FooterEnd);
             self.footor.insert(0, footer);
+            result.map_err(|e| self.band_error(BandKind::Footer, e))?;
         }
-        self.execute_replace_pagetotal();
+        Ok(())
+    }
+    fn execute_report_summary(
+        &mut self,
+        report_summary: &dyn exec::TryReportSummary,
+    ) -> Result<(), ReportError> {
+        let height = report_summary
+            .TryGetHeight(self)
+            .map_err(|e| self.band_error(BandKind::ReportSummary, e))?;
+        self.page_break_check(height)?;
+        report_summary
+            .TryExecute(self)
+            .map_err(|e| self.band_error(BandKind::ReportSummary, e))?;
+        self.cur_vpos = self.cur_vpos + height as f64;
+        Ok(())
     }
     pub fn execute_replace_pagetotal(&mut self) {
-        let mut total_page: String = String::new(); // Initialize as an empty String
+        let mut total_page: Option<i32> = None;
 
         // First pass: find TOTALPAGE
         for i in (0..self.buffer.len()).rev() {
-            let mut txt = self.buffer.remove(i);
-            // Create a new Vector<String> instead of Vec<&str>
-            let v: Vec<String> = txt.split("\t").map(|s| s.to_string()).collect();
-
-            if v[0] == "V" {
-                let page_temp = &v[2]; // Now a String, not a &str
-                let total_page_temp = v[4].clone(); // Now a String, not a &str
-
-                if total_page.is_empty() {
-                    total_page = total_page_temp; // Clone into total_page
+            if let Ok(BufferCommand::PageNumber {
+                page,
+                total_page: t,
+            }) = BufferCommand::parse(&self.buffer[i])
+            {
+                let total = *total_page.get_or_insert(t);
+                self.buffer[i] = BufferCommand::PageNumber {
+                    page,
+                    total_page: total,
                 }
-
-                //txt = txt.replace("TOTALPAGE", &total_page);
-                let parts: Vec<&str> = txt.split("\t").collect();
-                let mut new_txt = String::new();
-                for (i, part) in parts.iter().enumerate() {
-                    if i == 4 {
-                        new_txt.push_str(&total_page);
-                    } else {
-                        new_txt.push_str(part);
-                    }
-                    if i < parts.len() - 1 {
-                        new_txt.push('\t');
-                    }
-                }
-                txt = new_txt;
-
-                if *page_temp == "1" {
-                    total_page.clear(); // Clear the String
+                .serialize()
+                    + "\n";
+                if page == 1 {
+                    total_page = None;
                 }
             }
-            self.buffer.insert(i, txt);
         }
 
-        // Second pass: replace ＆＃TOTALPAGE&#
+        // Second pass: replace ＆＃TOTALPAGE&# and !!!name!!!
         let mut total_page_value = "".to_string();
         for i in 0..self.buffer.len() {
-            let mut txt = self.buffer.remove(i); // Remove and take ownership
-                                                 //parts now owns its String data, no more borrow from txt.
-            let parts: Vec<String> = txt.split("\t").map(|s| s.to_string()).collect();
-            let findtotal = r#"&#PAGETOTAL&#"#.to_string();
-            if txt.contains("PAGETOTAL") {
-                println!("found");
-            }
-            if parts.len() >= 3 && txt.contains(&findtotal) {
-                // if let Some(total_page_value) = total_pages.get(&current_page) {
-                txt = txt.replace(&findtotal, &total_page_value);
-                //}
-            }
-            if parts[0] == "V" && parts.len() >= 4 && parts[2] == "1" {
-                total_page_value = parts[4].clone();
-            }
-            if txt.contains("!!!") {
-                for i in 0..parts.len() {
-                    if parts[i].contains("!!!") {
-                        let  mut found = parts[i].clone().trim().to_string();
-                        print!("found {:?} {}", found, i);
-
-                        // HashMap::get() を使用して値を取得し、Option<&Value> を返す
-                        if let Some(work) = self.sum_work.get(&found) {
-                            println!("work {:?}", work);
-
-                            // work が数値の場合（total が数値だと仮定）
-                            if let Some(num) = work.as_f64() {
-                                let work_str = num.to_string();
-                                let mut new_txt = String::new();
-                                for (j, part) in parts.iter().enumerate() {
-                                    if j == i {
-                                        new_txt.push_str(&work_str);
-                                    } else {
-                                        new_txt.push_str(part);
-                                    }
-                                    if j < parts.len() - 1 {
-                                        new_txt.push('\t');
-                                    }
-                                }
-                                txt = new_txt;
-                            }
-                            // 文字列として扱いたい場合
-                            else if let Some(work_str) = work.as_str() {
-                                let mut new_txt = String::new();
-                                for (j, part) in parts.iter().enumerate() {
-                                    if j == i {
-                                        new_txt.push_str(work_str);
-                                    } else {
-                                        new_txt.push_str(part);
-                                    }
-                                    if j < parts.len() - 1 {
-                                        new_txt.push('\t');
-                                    }
-                                }
-                                txt = new_txt;
-                            }
+            let line = self.buffer[i].trim_end_matches('\n');
+            if let Ok(BufferCommand::PageNumber {
+                page: 1,
+                total_page,
+            }) = BufferCommand::parse(line)
+            {
+                total_page_value = total_page.to_string();
+                continue;
+            }
+            if !line.contains(command::PAGE_TOTAL_MARKER) && !line.contains("!!!") {
+                continue;
+            }
+            let parts: Vec<String> = line
+                .split("\t")
+                .map(|part| {
+                    let part = command::replace_page_total(part, &total_page_value);
+                    if !part.contains("!!!") {
+                        return part;
+                    }
+                    match self.sum_work.get(part.trim()) {
+                        // work が数値の場合（total が数値だと仮定）
+                        Some(work) if work.as_f64().is_some() => work.as_f64().unwrap().to_string(),
+                        // 文字列として扱いたい場合
+                        Some(work) if work.as_str().is_some() => {
+                            command::escape_text(work.as_str().unwrap())
                         }
+                        _ => part,
                     }
-                }
-            }
-            self.buffer.insert(i, txt);
+                })
+                .collect();
+            self.buffer[i] = parts.join("\t") + "\n";
         }
         // for i in 0..self.buffer.len() {
         //     let mut txt = self.buffer.remove(i); // Remove and take ownership
@@ -898,39 +2368,47 @@ impl Context {
     }
     // ... (rest of your Context implementation) ...
 
-    pub fn exec_detail(&mut self) {
-        let mut detail = self.detail.remove(0);
+    pub fn exec_detail(&mut self) -> Result<(), ReportError> {
+        let detail = self.detail.remove(0);
+        let result = self.execute_detail(detail.as_ref());
+        self.detail.insert(0, detail);
+        result
+    }
+    fn execute_detail(&mut self, detail: &dyn exec::TryDetail) -> Result<(), ReportError> {
         if self.flags.get("NewPageForce").is_some()
             && (*self.flags.get("NewPageForce").unwrap() == true)
         {
-            self.page_break(*self.flags.get("ResetPageNo").unwrap());
+            self.page_break(*self.flags.get("ResetPageNo").unwrap())?;
             self.flags.insert("NewPageForce".to_string(), false);
         }
         if self.max_level > 0 {
-            let bfr = detail.BreakCheckBefore(self);
+            let bfr = detail
+                .TryBreakCheckBefore(self)
+                .map_err(|e| self.band_error(BandKind::Detail, e))?;
             if bfr > 0 {
-                self.execute_group_header(bfr);
+                self.execute_group_header(bfr)?;
             }
         }
-        let height = detail.GetHeight(self);
-        self.page_break_check(height);
-        detail.Execute(self);
+        let height = detail
+            .TryGetHeight(self)
+            .map_err(|e| self.band_error(BandKind::Detail, e))?;
+        self.page_break_check(height)?;
+        detail
+            .TryExecute(self)
+            .map_err(|e| self.band_error(BandKind::Detail, e))?;
         if self.max_level > 0 {
-            let afr = detail.BreakCheckAfter(self);
+            let afr = detail
+                .TryBreakCheckAfter(self)
+                .map_err(|e| self.band_error(BandKind::Detail, e))?;
             if afr > 0 {
-                self.execute_group_summary(afr);
+                self.execute_group_summary(afr)?;
             }
         }
-
-        self.detail.insert(0, detail);
+        Ok(())
     }
 
-    pub fn page_break(&mut self, reset_page_no: bool) {
-        if self.footor.len() > 0 {
-            let footer = self.footor.remove(0);
-            footer.Execute(self);
-            self.footor.insert(0, footer);
-        }
+    pub fn page_break(&mut self, reset_page_no: bool) -> Result<(), ReportError> {
+        self.execute_footer()?;
         self.new_page();
         if reset_page_no {
             self.page = 1;
@@ -941,24 +2419,14 @@ impl Context {
         }
         self.page_report = self.page_report + 1;
         self.cur_vpos = 0.0;
-        self.buffer.push(
-            "V\tPAGE\t".to_string()
-                + self.page.to_string().as_str()
-                + "\tTOTALPAGE\t"
-                + self.page_total.to_string().as_str()
-                + "\n",
-        );
-        if (self.page_header.len() > 0) {
-            let header = self.page_header.remove(0);
-            header.Execute(self);
-            self.page_header.insert(0, header);
-        }
-        if self.page == 3 {
-            println!("page 3");
-        }
+        self.push_command(BufferCommand::PageNumber {
+            page: self.page,
+            total_page: self.page_total,
+        });
+        self.execute_page_header()
     }
 
-    pub fn execute_group_header(&mut self, level: i32) {
+    pub fn execute_group_header(&mut self, level: i32) -> Result<(), ReportError> {
         let mut execlevl = level;
         if execlevl > self.group_header.len() as i32 {
             execlevl = self.group_header.len() as i32;
@@ -966,15 +2434,28 @@ impl Context {
         if self.group_header.len() > 0 {
             for i in 0..execlevl {
                 let group_header = self.group_header.remove(i as usize);
-                let height = group_header.GetHeight(self);
-                self.page_break_check(height);
-                group_header.Execute(self);
+                let result = self.execute_group_header_band(group_header.as_ref());
                 self.group_header.insert(i as usize, group_header);
-                self.cur_vpos = self.cur_vpos + height as f64;
+                result?;
             }
         }
+        Ok(())
+    }
+    fn execute_group_header_band(
+        &mut self,
+        group_header: &dyn exec::TryGroupHeader,
+    ) -> Result<(), ReportError> {
+        let height = group_header
+            .TryGetHeight(self)
+            .map_err(|e| self.band_error(BandKind::GroupHeader, e))?;
+        self.page_break_check(height)?;
+        group_header
+            .TryExecute(self)
+            .map_err(|e| self.band_error(BandKind::GroupHeader, e))?;
+        self.cur_vpos = self.cur_vpos + height as f64;
+        Ok(())
     }
-    pub fn execute_group_summary(&mut self, level: i32) {
+    pub fn execute_group_summary(&mut self, level: i32) -> Result<(), ReportError> {
         let mut execlevl = level;
         if execlevl > self.summary.len() as i32 {
             execlevl = self.summary.len() as i32;
@@ -982,16 +2463,43 @@ impl Context {
 
         for i in 0..execlevl {
             let summary = self.summary.remove(i as usize);
-            let height = summary.GetHeight(self);
-            self.page_break_check(height);
-            summary.Execute(self);
+            let result = self.execute_summary_band(summary.as_ref());
             self.summary.insert(i as usize, summary);
+            result?;
             // self.cur_vpos = self.cur_vpos + height as f64;
         }
+        Ok(())
+    }
+    fn execute_summary_band(&mut self, summary: &dyn exec::TrySummary) -> Result<(), ReportError> {
+        let height = summary
+            .TryGetHeight(self)
+            .map_err(|e| self.band_error(BandKind::Summary, e))?;
+        self.page_break_check(height)?;
+        summary
+            .TryExecute(self)
+            .map_err(|e| self.band_error(BandKind::Summary, e))?;
+        Ok(())
     }
-    pub fn page_break_check(&mut self, height: f32) {
+    pub fn page_break_check(&mut self, height: f32) -> Result<(), ReportError> {
         if self.cur_vpos + height as f64 > self.footer_vpos {
-            self.page_break(false);
+            self.page_break(false)?;
         }
+        Ok(())
     }
 }
+
+/// Font metrics for genpdf. `path` is only used for errors.
+fn font_data(path: &str, bytes: Vec<u8>) -> Result<FontData, ConvertErrorKind> {
+    FontData::new(bytes, None).map_err(|e| ConvertErrorKind::FontFile {
+        path: path.to_string(),
+        message: e.to_string(),
+    })
+}
+
+/// Font for the PDF document. `path` is only used for errors.
+fn parse_font(path: &str, bytes: &[u8]) -> Result<ParsedFont, ConvertErrorKind> {
+    ParsedFont::from_bytes(bytes, 0).ok_or_else(|| ConvertErrorKind::FontFile {
+        path: path.to_string(),
+        message: "invalid font data".to_string(),
+    })
+}
@@ -0,0 +1,242 @@
+use std::fmt;
+use std::str::FromStr;
+
+/// Horizontal alignment of the lines of a text box.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum TextAlign {
+    #[default]
+    Left,
+    Right,
+    Center,
+    /// Lines are spread to the full width, except the last line of a
+    /// paragraph which is left aligned.
+    Justify,
+}
+
+/// Written as `L`, `R`, `C` or `J` in the `TB` buffer command.
+impl fmt::Display for TextAlign {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            TextAlign::Left => write!(f, "L"),
+            TextAlign::Right => write!(f, "R"),
+            TextAlign::Center => write!(f, "C"),
+            TextAlign::Justify => write!(f, "J"),
+        }
+    }
+}
+
+impl FromStr for TextAlign {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "L" => Ok(TextAlign::Left),
+            "R" => Ok(TextAlign::Right),
+            "C" => Ok(TextAlign::Center),
+            "J" => Ok(TextAlign::Justify),
+            _ => Err(format!("unknown text align: {}", s)),
+        }
+    }
+}
+
+/// How `write_text_fit` makes text fit its width.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TextFit {
+    /// Cuts the text and ends it with `…`.
+    Ellipsis,
+    /// Reduces the font size 1pt at a time, down to `min_size`. Text that
+    /// is still too wide at `min_size` is cut with `…`.
+    Shrink { min_size: i32 },
+}
+
+/// Written as `Ellipsis` or `Shrink:<min_size>` in the `TF` buffer command.
+impl fmt::Display for TextFit {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            TextFit::Ellipsis => write!(f, "Ellipsis"),
+            TextFit::Shrink { min_size } => write!(f, "Shrink:{}", min_size),
+        }
+    }
+}
+
+impl FromStr for TextFit {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s == "Ellipsis" {
+            return Ok(TextFit::Ellipsis);
+        }
+        s.strip_prefix("Shrink:")
+            .and_then(|min| min.parse().ok())
+            .map(|min_size| TextFit::Shrink { min_size })
+            .ok_or_else(|| format!("unknown text fit: {}", s))
+    }
+}
+
+/// A line produced by `wrap_text`.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct WrappedLine {
+    pub text: String,
+    /// The line ends a paragraph (explicit newline or end of text).
+    pub last: bool,
+}
+
+/// CJK characters can be broken before and after without a space, and stand
+/// upright in vertical text.
+pub(crate) fn is_cjk(c: char) -> bool {
+    matches!(c,
+        '\u{3000}'..='\u{30FF}'
+        | '\u{3400}'..='\u{4DBF}'
+        | '\u{4E00}'..='\u{9FFF}'
+        | '\u{AC00}'..='\u{D7AF}'
+        | '\u{F900}'..='\u{FAFF}'
+        | '\u{FF00}'..='\u{FFEF}')
+}
+
+/// Characters that must not start a line (禁則). They stay on the previous
+/// line even if it overflows slightly.
+fn no_break_before(c: char) -> bool {
+    "、。，．）」』】〕〉》！？ー…・：；".contains(c)
+}
+
+/// Splits a paragraph into units that cannot be broken: words, single CJK
+/// characters and no-break-before punctuation attached to the preceding
+/// unit. The flag is true if the unit was preceded by whitespace.
+fn units(paragraph: &str) -> Vec<(bool, String)> {
+    let mut units: Vec<(bool, String)> = Vec::new();
+    let mut space = false;
+    let mut word = String::new();
+    let mut word_space = false;
+    for c in paragraph.chars() {
+        if c.is_whitespace() {
+            if !word.is_empty() {
+                units.push((word_space, std::mem::take(&mut word)));
+            }
+            space = true;
+            continue;
+        }
+        if is_cjk(c) || no_break_before(c) {
+            if !word.is_empty() {
+                units.push((word_space, std::mem::take(&mut word)));
+            }
+            if no_break_before(c) && !space {
+                if let Some(last) = units.last_mut() {
+                    last.1.push(c);
+                    continue;
+                }
+            }
+            units.push((space, c.to_string()));
+            space = false;
+            continue;
+        }
+        if word.is_empty() {
+            word_space = space;
+        }
+        word.push(c);
+        space = false;
+    }
+    if !word.is_empty() {
+        units.push((word_space, word));
+    }
+    units
+}
+
+/// Wraps `text` to lines no wider than `width` as measured by `measure`.
+///
+/// Breaks at whitespace and between CJK characters, and at newlines. A
+/// word wider than `width` is broken between characters.
+pub(crate) fn wrap_text<E>(
+    text: &str,
+    width: f64,
+    measure: &mut dyn FnMut(&str) -> Result<f64, E>,
+) -> Result<Vec<WrappedLine>, E> {
+    let mut lines = Vec::new();
+    for paragraph in text.split('\n') {
+        let mut line = String::new();
+        for (space, unit) in units(paragraph) {
+            let candidate = if line.is_empty() {
+                unit.clone()
+            } else if space {
+                format!("{} {}", line, unit)
+            } else {
+                format!("{}{}", line, unit)
+            };
+            if measure(&candidate)? <= width {
+                line = candidate;
+                continue;
+            }
+            if !line.is_empty() {
+                lines.push(WrappedLine {
+                    text: std::mem::take(&mut line),
+                    last: false,
+                });
+            }
+            if measure(&unit)? <= width {
+                line = unit;
+                continue;
+            }
+            // too long for a line of its own
+            for c in unit.chars() {
+                let mut candidate = line.clone();
+                candidate.push(c);
+                if !line.is_empty() && measure(&candidate)? > width {
+                    lines.push(WrappedLine {
+                        text: std::mem::take(&mut line),
+                        last: false,
+                    });
+                    candidate = c.to_string();
+                }
+                line = candidate;
+            }
+        }
+        lines.push(WrappedLine {
+            text: line,
+            last: true,
+        });
+    }
+    Ok(lines)
+}
+
+/// Share of the em above the baseline, used to centre glyphs in the column
+/// of vertical text.
+pub(crate) const ASCENT: f64 = 0.88;
+
+/// Vertical presentation form of punctuation in vertical text (縦書き), e.g.
+/// `、` is drawn in the top right of its cell and brackets open downwards.
+pub(crate) fn vertical_form(c: char) -> Option<char> {
+    let form = match c {
+        '、' => '︑',
+        '。' => '︒',
+        '，' => '︐',
+        '：' => '︓',
+        '；' => '︔',
+        '！' => '︕',
+        '？' => '︖',
+        '…' => '︙',
+        '‥' => '︰',
+        '（' => '︵',
+        '）' => '︶',
+        '｛' => '︷',
+        '｝' => '︸',
+        '〔' => '︹',
+        '〕' => '︺',
+        '【' => '︻',
+        '】' => '︼',
+        '《' => '︽',
+        '》' => '︾',
+        '〈' => '︿',
+        '〉' => '﹀',
+        '「' => '﹁',
+        '」' => '﹂',
+        '『' => '﹃',
+        '』' => '﹄',
+        '－' => '︲',
+        _ => return None,
+    };
+    Some(form)
+}
+
+/// Characters turned a quarter clockwise in their cell of vertical text. The
+/// long vowel mark has no vertical presentation form, so its glyph is rotated
+/// and the text stays `ー`.
+pub(crate) fn turned_in_vertical(c: char) -> bool {
+    matches!(c, 'ー' | '〜')
+}
@@ -0,0 +1,50 @@
+/// A length in the drawing API.
+///
+/// Methods taking `impl Into<Length>` also accept a bare `f64`, which is
+/// millimetres as before. The buffer always stores millimetres, except `OS`
+/// which is points.
+///
+/// ```ignore
+/// context.write_text(Length::In(1.0), Length::In(0.5), "Invoice");
+/// context.write_text(25.4, 12.7, "Invoice"); // the same position in mm
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Length {
+    Mm(f64),
+    Pt(f64),
+    In(f64),
+}
+
+const MM_PER_INCH: f64 = 25.4;
+const PT_PER_INCH: f64 = 72.0;
+
+impl Length {
+    pub fn mm(self) -> f64 {
+        match self {
+            Length::Mm(v) => v,
+            Length::Pt(v) => v * MM_PER_INCH / PT_PER_INCH,
+            Length::In(v) => v * MM_PER_INCH,
+        }
+    }
+    pub fn pt(self) -> f64 {
+        match self {
+            Length::Mm(v) => v * PT_PER_INCH / MM_PER_INCH,
+            Length::Pt(v) => v,
+            Length::In(v) => v * PT_PER_INCH,
+        }
+    }
+    pub fn inch(self) -> f64 {
+        match self {
+            Length::Mm(v) => v / MM_PER_INCH,
+            Length::Pt(v) => v / PT_PER_INCH,
+            Length::In(v) => v,
+        }
+    }
+}
+
+/// A bare number is millimetres.
+impl From<f64> for Length {
+    fn from(mm: f64) -> Self {
+        Length::Mm(mm)
+    }
+}
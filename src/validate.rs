@@ -0,0 +1,277 @@
+use crate::command::{self, BufferCommand};
+use crate::error::ParseError;
+use crate::font::FontStyle;
+use crate::Context;
+use std::collections::HashSet;
+use std::fmt;
+use std::path::Path;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Severity {
+    /// The buffer converts, but the output is probably not what was intended.
+    Warning,
+    /// `convert` fails on this line.
+    Error,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum IssueKind {
+    /// The line is not a valid command, e.g. an unknown opcode.
+    Parse(ParseError),
+    /// `F` refers to a font not loaded with an earlier `FF`.
+    UnknownFont(String),
+    /// Text or `FST` before any font is set with `FF`, `F`, `FP` or `FM`.
+    NoFont,
+    /// The font file for `FF` is not found in its directory or `font_paths`.
+    MissingFontFile(String),
+    /// A point lies outside `page_width` x `page_height`.
+    OutsidePage { x: f64, y: f64 },
+    /// Text is drawn below `footer_vpos`, other than by a footer band
+    /// (between `FTS` and `FTE`).
+    BelowFooter { y: f64 },
+    /// `&#PAGETOTAL&#` or a `!!!name!!!` marker was not replaced.
+    UnresolvedMarker(String),
+    /// The image file does not exist.
+    MissingImage(String),
+}
+
+/// A problem found by `Context::validate_buffer`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Issue {
+    /// 1-based line number in `Context::buffer`.
+    pub line: usize,
+    pub opcode: String,
+    pub severity: Severity,
+    pub kind: IssueKind,
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Severity::Warning => write!(f, "warning"),
+            Severity::Error => write!(f, "error"),
+        }
+    }
+}
+
+impl fmt::Display for IssueKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            IssueKind::Parse(e) => write!(f, "{}", e),
+            IssueKind::UnknownFont(name) => write!(f, "font {:?} is not loaded", name),
+            IssueKind::NoFont => write!(f, "no font is set"),
+            IssueKind::MissingFontFile(path) => write!(f, "font file {} not found", path),
+            IssueKind::OutsidePage { x, y } => write!(f, "({}, {}) is outside the page", x, y),
+            IssueKind::BelowFooter { y } => write!(f, "text at {} is below the footer", y),
+            IssueKind::UnresolvedMarker(marker) => write!(f, "unresolved marker {}", marker),
+            IssueKind::MissingImage(path) => write!(f, "image {} not found", path),
+        }
+    }
+}
+
+impl fmt::Display for Issue {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "line {}: {}: {}: {}",
+            self.line, self.severity, self.opcode, self.kind
+        )
+    }
+}
+
+impl Context {
+    /// Checks the buffer without rendering it and returns the problems found,
+    /// in buffer order. An empty list means every font and file the buffer
+    /// uses is there, so `convert` fails only on unreadable or invalid files.
+    pub fn validate_buffer(&self) -> Vec<Issue> {
+        let mut issues = Vec::new();
+        let mut fonts: HashSet<String> = HashSet::new();
+        let mut font_set = false;
+        let mut page_width = self.page_width as f64;
+        let mut page_height = self.page_height as f64;
+        let mut next_page_size = None;
+        // `footer_vpos` is relative to the top margin; the buffer is not. It
+        // follows page size changes, so its distance from the bottom of the
+        // page is the same on every page.
+        let footer_abs = self.margins.top + self.footer_vpos;
+        let footer_margin = self.page_height as f64 - footer_abs;
+        let mut in_footer = false;
+        for (i, line) in self.buffer.iter().enumerate() {
+            let command = match BufferCommand::parse(line) {
+                Ok(command) => command,
+                Err(ParseError::Empty) => continue,
+                Err(e) => {
+                    issues.push(Issue {
+                        line: i + 1,
+                        opcode: line.split('\t').next().unwrap_or("").trim_end().to_string(),
+                        severity: Severity::Error,
+                        kind: IssueKind::Parse(e),
+                    });
+                    continue;
+                }
+            };
+            let mut issue = |severity: Severity, kind: IssueKind| {
+                issues.push(Issue {
+                    line: i + 1,
+                    opcode: command.opcode().to_string(),
+                    severity,
+                    kind,
+                })
+            };
+            match &command {
+                BufferCommand::Page { size, orientation } => {
+                    let (w, h) = size.dimensions(orientation);
+                    page_width = w as f64;
+                    page_height = h as f64;
+                }
+                BufferCommand::PageChange { size, orientation } => {
+                    next_page_size = Some(size.dimensions(orientation));
+                }
+                BufferCommand::FooterStart => in_footer = true,
+                BufferCommand::FooterEnd => in_footer = false,
+                BufferCommand::NewPage => {
+                    if let Some((w, h)) = next_page_size.take() {
+                        page_width = w as f64;
+                        page_height = h as f64;
+                    }
+                }
+                BufferCommand::PageMm { width, height } => {
+                    page_width = *width as f64;
+                    page_height = *height as f64;
+                }
+                BufferCommand::FontFile { name, dir } => {
+                    let dir = self.resolve_font_dir(dir, name);
+                    let path = format!("{}/{}-Regular.ttf", dir, name);
+                    if !Path::new(&path).is_file() {
+                        issue(Severity::Error, IssueKind::MissingFontFile(path));
+                    }
+                    fonts.insert(name.clone());
+                    font_set = true;
+                }
+                BufferCommand::Font { name } => {
+                    if fonts.contains(name) {
+                        font_set = true;
+                    } else {
+                        issue(Severity::Error, IssueKind::UnknownFont(name.clone()));
+                    }
+                }
+                BufferCommand::FontStyle(_) if !font_set => {
+                    issue(Severity::Error, IssueKind::NoFont);
+                }
+                BufferCommand::FontPath { name, style, path } => {
+                    if !Path::new(path).is_file() {
+                        issue(Severity::Error, IssueKind::MissingFontFile(path.clone()));
+                    }
+                    if *style == FontStyle::Regular {
+                        fonts.insert(name.clone());
+                        font_set = true;
+                    }
+                }
+                BufferCommand::FontMemory { name, style } => {
+                    if !self.font_bytes.contains_key(&style.key(name)) {
+                        issue(Severity::Error, IssueKind::UnknownFont(style.key(name)));
+                    }
+                    if *style == FontStyle::Regular {
+                        fonts.insert(name.clone());
+                        font_set = true;
+                    }
+                }
+                BufferCommand::FontFallback { fallbacks, .. } => {
+                    for name in fallbacks.iter().filter(|f| !fonts.contains(*f)) {
+                        issue(Severity::Error, IssueKind::UnknownFont(name.clone()));
+                    }
+                }
+                BufferCommand::Image { path, .. } if !Path::new(path).is_file() => {
+                    issue(Severity::Error, IssueKind::MissingImage(path.clone()));
+                }
+                _ => {}
+            }
+            if !font_set && command.text().is_some() {
+                issue(Severity::Error, IssueKind::NoFont);
+            }
+            if page_width > 0.0 && page_height > 0.0 {
+                for (x, y) in points(&command) {
+                    if x < 0.0 || y < 0.0 || x > page_width || y > page_height {
+                        issue(Severity::Warning, IssueKind::OutsidePage { x, y });
+                    }
+                }
+            }
+            if let BufferCommand::TextLeft { y, .. }
+            | BufferCommand::TextRight { y, .. }
+            | BufferCommand::TextCenter { y, .. }
+            | BufferCommand::TextBox { y, .. }
+            | BufferCommand::TextFit { y, .. }
+            | BufferCommand::TextRotated { y, .. }
+            | BufferCommand::TextVertical { y, .. }
+            | BufferCommand::TextJustify { y1: y, .. } = &command
+            {
+                let footer_vpos = if page_height > 0.0 && self.page_height > 0.0 {
+                    page_height - footer_margin
+                } else {
+                    footer_abs
+                };
+                if self.footer_vpos > 0.0 && !in_footer && *y > footer_vpos {
+                    issue(Severity::Warning, IssueKind::BelowFooter { y: *y });
+                }
+            }
+            if let Some(text) = command.text() {
+                for marker in markers(text) {
+                    issue(Severity::Warning, IssueKind::UnresolvedMarker(marker));
+                }
+            }
+        }
+        issues
+    }
+}
+
+/// Points drawn by a command, in mm from the top left of the page.
+fn points(command: &BufferCommand) -> Vec<(f64, f64)> {
+    match *command {
+        BufferCommand::TextLeft { x, y, .. }
+        | BufferCommand::TextRight { x, y, .. }
+        | BufferCommand::TextCenter { x, y, .. }
+        | BufferCommand::TextRotated { x, y, .. }
+        | BufferCommand::TextVertical { x, y, .. } => vec![(x, y)],
+        BufferCommand::TextBox { x, y, width, .. } | BufferCommand::TextFit { x, y, width, .. } => {
+            vec![(x, y), (x + width, y)]
+        }
+        BufferCommand::TextJustify { x1, y1, x2, .. } => vec![(x1, y1), (x2, y1)],
+        BufferCommand::LineHorizontal { x1, y1, x2 } => vec![(x1, y1), (x2, y1)],
+        BufferCommand::LineHorizontalStrlen { x1, y1, .. }
+        | BufferCommand::LineHorizontalRightStrlen { x1, y1, .. } => vec![(x1, y1)],
+        BufferCommand::LineVertical { x1, y1, y2 } => vec![(x1, y1), (x1, y2)],
+        BufferCommand::Line { x1, y1, x2, y2 } | BufferCommand::Rect { x1, y1, x2, y2, .. } => {
+            vec![(x1, y1), (x2, y2)]
+        }
+        BufferCommand::Image { x, y, w, h, .. } => vec![(x, y), (x + w, y + h)],
+        _ => Vec::new(),
+    }
+}
+
+/// `&#PAGETOTAL&#` and `!!!name!!!` markers left in a text field. Markers
+/// escaped by `command::escape_markers` are text, and so are `!!!` not
+/// around a name, e.g. in `SALE!!! ends soon !!!`.
+fn markers(text: &str) -> Vec<String> {
+    let mut found = Vec::new();
+    if command::replace_page_total(text, "") != text {
+        found.push(command::PAGE_TOTAL_MARKER.to_string());
+    }
+    let mut rest = text;
+    while let Some(start) = rest.find("!!!") {
+        let escaped = rest[..start].ends_with(command::MARKER_ESCAPE);
+        let after = &rest[start + 3..];
+        match after.find("!!!") {
+            Some(len) if !escaped && is_marker_name(&after[..len]) => {
+                found.push(rest[start..start + 3 + len + 3].to_string());
+                rest = &after[len + 3..];
+            }
+            _ => rest = after,
+        }
+    }
+    found
+}
+
+/// A `sum_work` name between `!!!`: not empty, without spaces or `!`.
+fn is_marker_name(name: &str) -> bool {
+    !name.is_empty() && !name.contains(|c: char| c.is_whitespace() || c == '!')
+}
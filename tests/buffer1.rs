@@ -0,0 +1,431 @@
+#[cfg(test)]
+mod buffer1 {
+    use rustreport::*;
+
+    /// Reads a buffer written by an earlier version and checks that every
+    /// line serializes back to itself.
+    fn round_trip(filename: &str) -> Vec<BufferCommand> {
+        let mut context = Context::new();
+        context
+            .read_buffer(filename)
+            .unwrap_or_else(|e| panic!("{}: {}", filename, e));
+        let mut commands = Vec::new();
+        for line in context.buffer.iter() {
+            let line = line.trim_end_matches('\n');
+            if line.trim().is_empty() {
+                continue;
+            }
+            let command = BufferCommand::parse(line)
+                .unwrap_or_else(|e| panic!("{}: {:?}: {}", filename, line, e));
+            assert_eq!(command.serialize(), line);
+            assert_eq!(
+                BufferCommand::parse(&command.serialize()),
+                Ok(command.clone())
+            );
+            commands.push(command);
+        }
+        commands
+    }
+
+    /// The texts drawn on a page, in order.
+    fn page_texts(page: &printpdf::PdfPage) -> Vec<String> {
+        page.ops
+            .iter()
+            .filter_map(|op| match op {
+                printpdf::Op::WriteText { text, .. } => Some(text.clone()),
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn buffer1_round_trip() {
+        // format 1 buffers written before BufferCommand, kept apart from the
+        // temp/ files the report tests write
+        round_trip("tests/buffer1_simple1.txt");
+        round_trip("tests/buffer1_medium1.txt");
+        round_trip("tests/buffer1_complex1.txt");
+        // a replaced !!!name!!! marker glued the next command to the text
+        let commands = round_trip("tests/buffer1_complex2.txt");
+        let total = commands
+            .iter()
+            .position(|c| c.text() == Some("¥83,545"))
+            .unwrap();
+        assert_eq!(
+            commands[total + 1],
+            BufferCommand::parse("LH\t25\t74\t99").unwrap()
+        );
+        assert_eq!(
+            command::upgrade_v1_line("TL\t1\t2\ta\tb"),
+            vec!["TL\t1\t2\ta\\tb".to_string()]
+        );
+    }
+
+    #[test]
+    fn buffer1_commands() {
+        let mut context = Context::new();
+        context.set_page(PageSize::A4, PageOrientation::Portrait);
+        context.set_font_size(12);
+        context.write_text(10.0, 20.5, "Sales Report");
+        context.write_rect(11.0, 106.0, 199.0, 113.5, true);
+        context.new_page();
+        let commands = context.commands().unwrap();
+        assert_eq!(
+            commands,
+            vec![
+                BufferCommand::Page {
+                    size: PageSize::A4,
+                    orientation: PageOrientation::Portrait,
+                },
+                BufferCommand::FontSize(12),
+                BufferCommand::TextLeft {
+                    x: 10.0,
+                    y: 20.5,
+                    text: "Sales Report".to_string(),
+                },
+                BufferCommand::Rect {
+                    x1: 11.0,
+                    y1: 106.0,
+                    x2: 199.0,
+                    y2: 113.5,
+                    fill: true,
+                },
+                BufferCommand::NewPage,
+            ]
+        );
+        assert_eq!(
+            context.get_buffer(),
+            "P\tA4\tPortrait\nFS\t12\nTL\t10\t20.5\tSales Report\nR\t11\t106\t199\t113.5\tY\nNP\n"
+        );
+    }
+
+    #[test]
+    fn buffer1_parse_errors() {
+        assert_eq!(
+            BufferCommand::parse("XX\t1"),
+            Err(ParseError::UnknownOpcode("XX".to_string()))
+        );
+        assert_eq!(
+            BufferCommand::parse("FS\tabc"),
+            Err(ParseError::InvalidField {
+                opcode: "FS".to_string(),
+                index: 1,
+                value: "abc".to_string(),
+            })
+        );
+        assert_eq!(
+            BufferCommand::parse("LH\t1\t2"),
+            Err(ParseError::MissingField {
+                opcode: "LH".to_string(),
+                index: 3,
+            })
+        );
+    }
+
+    #[test]
+    fn buffer1_escape() {
+        let text = "Name\twith tab\nnew line \\ back slash 山田\r\n";
+        let mut context = Context::new();
+        context.write_text(10.0, 20.0, text);
+        context.write_text_right(100.0, 20.0, "\\t");
+        context.write_line_horizontal_strlen(10.0, 22.0, text.to_string(), 0.0, 0.0);
+        assert_eq!(context.buffer.len(), 3);
+        assert_eq!(context.buffer[1], "TR\t100\t20\t\\\\t\n");
+        context.write_buffer("temp/buffer1.txt").unwrap();
+        let mut read = Context::new();
+        read.read_buffer("temp/buffer1.txt").unwrap();
+        assert_eq!(read.buffer[1..].join(""), context.get_buffer());
+        let commands = read.commands().unwrap();
+        assert_eq!(commands[0], command::version_header());
+        assert_eq!(
+            commands[1],
+            BufferCommand::TextLeft {
+                x: 10.0,
+                y: 20.0,
+                text: text.to_string(),
+            }
+        );
+        assert_eq!(
+            commands[2],
+            BufferCommand::TextRight {
+                x: 100.0,
+                y: 20.0,
+                text: "\\t".to_string(),
+            }
+        );
+        assert!(BufferCommand::parse("TL\t1\t2\tbad \\x escape").is_err());
+    }
+
+    #[test]
+    fn buffer1_literal_marker() {
+        let customer = "ACME &#PAGETOTAL&# !!!TOTAL!!! \\!!!";
+        let mut context = Context::new();
+        context.set_page(PageSize::A4, PageOrientation::Portrait);
+        context.set_font_dir_and_name("assets/fonts/Roboto/static", "Roboto");
+        context.set_font_size(12);
+        context.push_command(BufferCommand::PageNumber {
+            page: 1,
+            total_page: 3,
+        });
+        context
+            .sum_work
+            .insert("!!!TOTAL!!!".to_string(), serde_json::Value::from(5));
+        context.write_text(10.0, 20.0, "Page &#PAGETOTAL&#");
+        context.write_text(10.0, 30.0, &command::escape_markers(customer));
+        context.write_text(10.0, 40.0, &command::escape_markers("!!!TOTAL!!!"));
+        context.write_text(10.0, 50.0, "!!!TOTAL!!!");
+        // a backslash nobody escaped is drawn as is
+        context.write_text(10.0, 60.0, "C:\\!!!x \\&#PAGETOTAL&#");
+        context.execute_replace_pagetotal();
+        assert!(context.validate_buffer().is_empty());
+        context.convert().unwrap();
+        let texts = page_texts(&context.pages[0]);
+        assert_eq!(
+            texts,
+            vec!["Page 3", customer, "!!!TOTAL!!!", "5", "C:\\!!!x \\3"]
+        );
+        assert_eq!(
+            command::unescape_markers(&command::escape_markers(customer)),
+            customer
+        );
+    }
+
+    #[test]
+    fn buffer1_windows_paths() {
+        // paths are not text fields, their backslashes stay as written
+        for line in [
+            "FF\tNotoSansJP\tassets\\fonts\\Noto_Sans_JP\\static",
+            "FP\tRoboto\tBold\tC:\\fonts\\!!!\\Roboto-Bold.ttf",
+            "I\t10\t20\t30\t40\tC:\\images\\\\n\\logo.png",
+        ] {
+            let command = BufferCommand::parse(line).unwrap();
+            assert_eq!(command.text(), None);
+            assert_eq!(command.serialize(), line);
+        }
+        let path = "C:\\images\\!!!\\logo.png";
+        let mut context = Context::new();
+        context.set_page(PageSize::A4, PageOrientation::Portrait);
+        context.write_image(10.0, 20.0, 30.0, 40.0, path);
+        context.execute_replace_pagetotal();
+        let e = context.convert().unwrap_err();
+        assert!(matches!(e.kind, ConvertErrorKind::Image { path: ref p, .. } if p == path));
+    }
+
+    #[test]
+    fn buffer1_version() {
+        let mut context = Context::new();
+        context.write_text(10.0, 20.0, "a\\b");
+        context.write_buffer("temp/buffer1_version.txt").unwrap();
+        let txt = std::fs::read_to_string("temp/buffer1_version.txt").unwrap();
+        assert_eq!(
+            txt.lines().next().unwrap(),
+            format!("VER\t2\t{}", env!("CARGO_PKG_VERSION"))
+        );
+
+        // the header of a buffer written by another version is kept
+        std::fs::write("temp/buffer1_version.txt", "VER\t2\t0.1.0\nFS\t12\n").unwrap();
+        let mut read = Context::new();
+        read.read_buffer("temp/buffer1_version.txt").unwrap();
+        assert_eq!(read.buffer[0], "VER\t2\t0.1.0\n");
+        read.write_buffer("temp/buffer1_version.txt").unwrap();
+        let txt = std::fs::read_to_string("temp/buffer1_version.txt").unwrap();
+        assert_eq!(txt, "VER\t2\t0.1.0\nFS\t12\n");
+
+        // format 1 buffers have no header and unescaped text
+        std::fs::write(
+            "temp/buffer1_version.txt",
+            "FS\t12\nTL\t10\t20\ta\\b\tc\nLHS\t10\t22\tx\ty\t0\t1\n",
+        )
+        .unwrap();
+        let mut read = Context::new();
+        read.read_buffer("temp/buffer1_version.txt").unwrap();
+        let commands = read.commands().unwrap();
+        assert_eq!(
+            commands[1],
+            BufferCommand::TextLeft {
+                x: 10.0,
+                y: 20.0,
+                text: "a\\b\tc".to_string(),
+            }
+        );
+        assert_eq!(
+            commands[2],
+            BufferCommand::LineHorizontalStrlen {
+                x1: 10.0,
+                y1: 22.0,
+                text: "x\ty".to_string(),
+                offset_left: 0.0,
+                offset_right: 1.0,
+            }
+        );
+
+        std::fs::write("temp/buffer1_version.txt", "VER\t99\t9.0.0\nFS\t12\n").unwrap();
+        match read.read_buffer("temp/buffer1_version.txt") {
+            Err(BufferError::UnsupportedVersion { found, supported }) => {
+                assert_eq!(found, 99);
+                assert_eq!(supported, command::BUFFER_FORMAT_VERSION);
+            }
+            _ => panic!("newer buffer format must be refused"),
+        }
+    }
+
+    #[test]
+    fn buffer1_convert_error() {
+        let mut context = Context::new();
+        context.set_font_size(12);
+        context.set_font("NotLoaded");
+        let e = context.convert().unwrap_err();
+        assert_eq!(e.line, 2);
+        assert_eq!(e.opcode, "F");
+        assert!(matches!(e.kind, ConvertErrorKind::UnknownFont(ref name) if name == "NotLoaded"));
+
+        let mut context = Context::new();
+        context.buffer.push("FS\t12\n".to_string());
+        context.buffer.push("XX\t1\n".to_string());
+        let e = context.convert().unwrap_err();
+        assert_eq!(e.line, 2);
+        assert_eq!(e.opcode, "XX");
+        assert_eq!(e.to_string(), "line 2: XX: unknown command: XX");
+        // no incomplete PDF after a failed convert
+        let e = context.get_pdf_bytes().unwrap_err();
+        assert_eq!((e.line, e.opcode.as_str()), (2, "XX"));
+        let e = context.save("temp/buffer1_convert_error.pdf").unwrap_err();
+        assert_eq!(e.line, 2);
+        assert!(!std::path::Path::new("temp/buffer1_convert_error.pdf").exists());
+
+        let mut context = Context::new();
+        context.convert().unwrap();
+        let e = context.save("temp/no_such_dir/buffer1.pdf").unwrap_err();
+        assert!(matches!(e.kind, ConvertErrorKind::Save(_)));
+    }
+
+    #[test]
+    fn buffer1_convert_reader() {
+        let mut context = Context::new();
+        context.set_page(PageSize::A4, PageOrientation::Portrait);
+        context.set_font_dir_and_name("assets/fonts/Roboto/static", "Roboto");
+        context.write_text(10.0, 20.0, "page 1");
+        context.new_page();
+        context.write_text(10.0, 20.0, "page 2");
+        context.write_buffer("temp/buffer1_reader.txt").unwrap();
+
+        let file = std::fs::File::open("temp/buffer1_reader.txt").unwrap();
+        let mut streamed = Context::new();
+        streamed
+            .convert_reader(std::io::BufReader::new(file))
+            .unwrap();
+        assert_eq!(streamed.pages.len(), 2);
+        assert!(streamed.buffer.is_empty());
+        // both pages use the same layers
+        let layers = |page: &printpdf::PdfPage| -> Vec<printpdf::LayerInternalId> {
+            page.ops
+                .iter()
+                .filter_map(|op| match op {
+                    printpdf::Op::BeginLayer { layer_id } => Some(layer_id.clone()),
+                    _ => None,
+                })
+                .collect()
+        };
+        assert_eq!(layers(&streamed.pages[0]).len(), 2);
+        assert_eq!(layers(&streamed.pages[0]), layers(&streamed.pages[1]));
+
+        let mut read = Context::new();
+        read.read_buffer("temp/buffer1_reader.txt").unwrap();
+        read.convert().unwrap();
+        assert_eq!(read.pages.len(), 2);
+
+        // format 1 input is upgraded and line numbers count from the input
+        let mut streamed = Context::new();
+        let e = streamed
+            .convert_reader("FS\t12\nTL\t1\t2\ta\tb\nXX\n".as_bytes())
+            .unwrap_err();
+        assert_eq!(e.line, 3);
+        assert_eq!(e.opcode, "XX");
+    }
+
+    #[test]
+    fn buffer1_convert_reader_batches() {
+        let mut context = Context::new();
+        context.set_page(PageSize::A4, PageOrientation::Portrait);
+        context.set_font_dir_and_name("assets/fonts/Roboto/static", "Roboto");
+        for page in 1..=5 {
+            if page > 1 {
+                context.new_page();
+            }
+            context.write_text(10.0, 20.0, &format!("page {}", page));
+        }
+        context.write_buffer("temp/buffer1_batches.txt").unwrap();
+
+        let file = std::fs::File::open("temp/buffer1_batches.txt").unwrap();
+        let mut streamed = Context::new();
+        let mut batches = 0;
+        streamed
+            .convert_reader_batches(std::io::BufReader::new(file), 2, |_| {
+                batches += 1;
+                Ok(())
+            })
+            .unwrap();
+        assert_eq!(batches, 3);
+        assert!(streamed.pages.is_empty());
+
+        let mut streamed = Context::new();
+        let e = streamed
+            .convert_reader_batches("FS\t12\nXX\n".as_bytes(), 1, |_| Ok(()))
+            .unwrap_err();
+        assert_eq!((e.line, e.opcode.as_str()), (2, "XX"));
+        assert!(streamed.get_pdf_bytes().is_err());
+    }
+
+    #[test]
+    fn buffer1_save_to() {
+        let mut context = Context::new();
+        context.set_page(PageSize::A4, PageOrientation::Portrait);
+        context.set_font_dir_and_name("assets/fonts/Roboto/static", "Roboto");
+        context.write_text(10.0, 20.0, "page 1");
+        context.new_page();
+        context.write_text(10.0, 20.0, "page 2");
+        context.convert().unwrap();
+        let mut first = Vec::new();
+        context
+            .save_to(&mut first, &context.save_options())
+            .unwrap();
+        let mut second = Vec::new();
+        context
+            .save_to(&mut second, &context.save_options())
+            .unwrap();
+        assert_eq!(first.len(), second.len());
+        assert_eq!(context.get_pdf_bytes().unwrap().len(), first.len());
+        assert_eq!(context.pages.len(), 2);
+        assert!(context.doc.pages.is_empty());
+    }
+
+    #[test]
+    fn buffer1_page_size() {
+        let mut context = Context::new();
+        context.set_page(PageSize::B5Jis, PageOrientation::Landscape);
+        assert_eq!((context.page_width, context.page_height), (257.0, 182.0));
+        let envelope = PageSize::Custom {
+            width_mm: 90.5,
+            height_mm: 205.0,
+        };
+        context.set_page(envelope.clone(), PageOrientation::Portrait);
+        assert_eq!((context.page_width, context.page_height), (90.5, 205.0));
+        assert_eq!(context.buffer[1], "P\tCustom\tPortrait\t90.5\t205\n");
+        assert_eq!(
+            context.commands().unwrap()[1],
+            BufferCommand::Page {
+                size: envelope,
+                orientation: PageOrientation::Portrait,
+            }
+        );
+        assert_eq!(
+            BufferCommand::parse("P\tEnvelopeNaga3\tPortrait").unwrap(),
+            BufferCommand::Page {
+                size: PageSize::EnvelopeNaga3,
+                orientation: PageOrientation::Portrait,
+            }
+        );
+        assert!(BufferCommand::parse("P\tCustom\tPortrait\t90").is_err());
+        assert!(BufferCommand::parse("P\tB6\tPortrait").is_err());
+    }
+}
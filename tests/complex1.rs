@@ -61,10 +61,10 @@ mod complex1 {
         context.summary.push(Box::new(SummaryC2 {}));
         context.footor.push(Box::new(FooterC1 {}));
         context.max_level = 2;
-        context.exec();
-        context.convert();
-        context.save("temp/complex1.pdf");
-        context.write_buffer("temp/complex1.txt");
+        context.exec().unwrap();
+        context.convert().unwrap();
+        context.save("temp/complex1.pdf").unwrap();
+        context.write_buffer("temp/complex1.txt").unwrap();
         struct DetailC1;
         impl exec::Detail for DetailC1 {
             fn GetHeight(&self, context: &mut Context) -> f32 {
@@ -405,7 +405,7 @@ mod complex1 {
                         + " USD"),
                 );
                 if context.cur_line < context.input.len() as i32 - 1 {
-                    context.page_break(true);
+                    context.page_break(true).unwrap();
                 }
                 context
                     .sum_work
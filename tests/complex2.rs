@@ -3,7 +3,7 @@ mod complex2 {
     use num_format::Locale::ar_AE;
     use num_format::*; // for formatting floats
     use printpdf::*;
-    use rustreport::exec::{Detail, PageHeader, Summary};
+    use rustreport::exec::{PageHeader, Summary, TryDetail};
     use rustreport::PageOrientation::{Landscape, Portrait};
     use rustreport::PageSize::A4;
     use rustreport::*;
@@ -57,17 +57,17 @@ mod complex2 {
         context.summary.push(Box::new(SummaryC2G2 {}));
         context.footor.push(Box::new(FooterC2 {}));
         context.max_level = 2;
-        context.exec();
-        context.convert();
-        context.save("temp/complex2.pdf");
-        context.write_buffer("temp/complex2.txt");
+        context.exec().unwrap();
+        context.convert().unwrap();
+        context.save("temp/complex2.pdf").unwrap();
+        context.write_buffer("temp/complex2.txt").unwrap();
 
         struct DetailC2 {}
-        impl Detail for DetailC2 {
-            fn GetHeight(&self, context: &mut Context) -> f32 {
-                5.0
+        impl TryDetail for DetailC2 {
+            fn TryGetHeight(&self, context: &mut Context) -> exec::BandResult<f32> {
+                Ok(5.0)
             }
-            fn Execute(&self, context: &mut Context) {
+            fn TryExecute(&self, context: &mut Context) -> exec::BandResult<()> {
                 let mut slipshow = true;
                 if context.sum_work.get("g1item").unwrap().as_f64().unwrap() > 0.0 {
                     if context.input[context.cur_line as usize][4] == context.input[context.cur_line as usize - 1][4] &&
@@ -108,7 +108,7 @@ mod complex2 {
                 context.write_text_right(
                     x + 115.0,
                     y + 4.0,
-                    exec::i64_to_string_comma(s5.as_str().parse::<i64>().unwrap()).as_str(),
+                    exec::i64_to_string_comma(s5.as_str().parse::<i64>()?).as_str(),
                 );
 
                 context.write_text_right(
@@ -118,8 +118,7 @@ mod complex2 {
                         + &exec::i64_to_string_comma(
                         context.input[context.cur_line as usize][8]
                             .as_str()
-                            .parse::<i64>()
-                            .unwrap(),
+                            .parse::<i64>()?,
                     )),
                 );
                 context.write_text_right(
@@ -128,14 +127,12 @@ mod complex2 {
                     &exec::i64_to_string_comma(
                         context.input[context.cur_line as usize][9]
                             .as_str()
-                            .parse::<i64>()
-                            .unwrap(),
+                            .parse::<i64>()?,
                     ),
                 );
                 let amt = context.input[context.cur_line as usize][9]
                     .as_str()
-                    .parse::<f64>()
-                    .unwrap();
+                    .parse::<f64>()?;
                 context.sum_work.insert(
                     "g1amtcum".to_string(),
                     serde_json::Value::from(
@@ -161,40 +158,41 @@ mod complex2 {
                     ),
                 );
                 context.cur_vpos += 5.0;
+                Ok(())
             }
 
 
-            fn BreakCheckBefore(&self, context: &mut Context) -> i32 {
+            fn TryBreakCheckBefore(&self, context: &mut Context) -> exec::BandResult<i32> {
                 if context.cur_line == 0 {
-                    return 2 as i32;
+                    return Ok(2);
                 }
                 if context.input[context.cur_line as usize][0]
                     != context.input[context.cur_line as usize - 1][0]
                 {
-                    return 2 as i32;
+                    return Ok(2);
                 }
                 if context.input[context.cur_line as usize][4]
                     != context.input[context.cur_line as usize - 1][4]
                 {
-                    return 1 as i32;
+                    return Ok(1);
                 }
-                0 as i32
+                Ok(0)
             }
-            fn BreakCheckAfter(&self, context: &mut Context) -> i32 {
+            fn TryBreakCheckAfter(&self, context: &mut Context) -> exec::BandResult<i32> {
                 if context.cur_line == context.input.len() as i32 - 1 {
-                    return 2 as i32;
+                    return Ok(2);
                 }
                 if context.input[context.cur_line as usize][0]
                     != context.input[context.cur_line as usize + 1][0]
                 {
-                    return 2 as i32;
+                    return Ok(2);
                 }
                 if context.input[context.cur_line as usize][4]
                     != context.input[context.cur_line as usize + 1][4]
                 {
-                    return 1 as i32;
+                    return Ok(1);
                 }
-                0 as i32
+                Ok(0)
             }
         }
 
@@ -396,7 +394,7 @@ mod complex2 {
                 //     .sum_work
                 //     .insert("g2amtcum".to_string(), serde_json::Value::from(0.0 as f64));
                 if context.cur_line< context.input.len() as i32 - 1 {
-                    context.page_break(true)
+                    context.page_break(true).unwrap();
                 }
             }
         }
@@ -55,10 +55,10 @@ mod medium1 {
         context.summary.push(Box::new(GroupSummaryM11 {}));
         context.summary.push(Box::new(GroupSummaryM12 {}));
         context.max_level = 2;
-        context.exec();
-        context.convert();
-        context.save("temp/medium1.pdf");
-        context.write_buffer("temp/medium1.txt");
+        context.exec().unwrap();
+        context.convert().unwrap();
+        context.save("temp/medium1.pdf").unwrap();
+        context.write_buffer("temp/medium1.txt").unwrap();
     }
     struct DetailM1;
     impl exec::Detail for DetailM1 {
@@ -301,7 +301,7 @@ mod medium1 {
                     .as_str(),
             );
             context.sum_work.insert("g2cum".to_string(), serde_json::Value::from(0.0 as f64));
-            context.page_break(false);
+            context.page_break(false).unwrap();
             //Page breakの時は vpos がリセットされるので、ここで設定不要
          }
     }
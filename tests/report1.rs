@@ -0,0 +1,307 @@
+#[cfg(test)]
+mod report1 {
+    use rustreport::exec::BandResult;
+    use rustreport::*;
+
+    struct DetailR1 {}
+    impl exec::TryDetail for DetailR1 {
+        fn TryGetHeight(&self, _context: &mut Context) -> BandResult<f32> {
+            Ok(5.0)
+        }
+        fn TryExecute(&self, context: &mut Context) -> BandResult<()> {
+            let amount = context.input[context.cur_line as usize][0].parse::<f64>()?;
+            context.write_text(10.0, context.cur_vpos + 4.0, &amount.to_string());
+            context.cur_vpos += 5.0;
+            Ok(())
+        }
+    }
+
+    struct PageHeaderR1 {}
+    impl exec::PageHeader for PageHeaderR1 {
+        fn Execute(&self, context: &mut Context) {
+            context.write_text(10.0, 5.0, "Page &#PAGETOTAL&#");
+            context.cur_vpos = 10.0;
+        }
+    }
+
+    struct FooterR1 {}
+    impl exec::Footer for FooterR1 {
+        fn Execute(&self, context: &mut Context) {
+            context.write_text(10.0, 285.0, "footer");
+        }
+    }
+
+    /// Switches to landscape after the first row.
+    struct DetailR2 {}
+    impl exec::Detail for DetailR2 {
+        fn GetHeight(&self, _context: &mut Context) -> f32 {
+            150.0
+        }
+        fn Execute(&self, context: &mut Context) {
+            if context.cur_line == 0 {
+                context.set_next_page(PageSize::A4, PageOrientation::Landscape);
+            }
+            context.cur_vpos += 150.0;
+        }
+    }
+
+    struct PageHeaderR2 {}
+    impl exec::PageHeader for PageHeaderR2 {
+        fn Execute(&self, context: &mut Context) {
+            let text = format!(
+                "{}x{} {}",
+                context.page_width, context.page_height, context.footer_vpos
+            );
+            context.write_text(10.0, 5.0, &text);
+            context.cur_vpos = 10.0;
+        }
+    }
+
+    /// Measures its text while laying out.
+    struct DetailR3 {}
+    impl exec::TryDetail for DetailR3 {
+        fn TryGetHeight(&self, context: &mut Context) -> BandResult<f32> {
+            let text = &context.input[context.cur_line as usize][0];
+            Ok(context.measure_text_box(40.0, text)? as f32)
+        }
+        fn TryExecute(&self, context: &mut Context) -> BandResult<()> {
+            let text = context.input[context.cur_line as usize][0].clone();
+            let width = context.string_width(&text)?;
+            context.write_text(10.0 + width, context.cur_vpos + 4.0, &text);
+            context.cur_vpos += 5.0;
+            Ok(())
+        }
+    }
+
+    fn report(input: Vec<Vec<String>>) -> Context {
+        let mut context = Context::new();
+        context.footer_vpos = 280.0;
+        context.input = input;
+        context.detail.push(Box::new(DetailR1 {}));
+        context
+    }
+
+    #[test]
+    fn report1_exec() {
+        let mut context = report(vec![vec!["1".to_string()], vec!["2.5".to_string()]]);
+        context.exec().unwrap();
+        assert!(context.get_buffer().contains("TL\t10\t9\t2.5\n"));
+    }
+
+    #[test]
+    fn report1_footer() {
+        let input = (0..60).map(|i| vec![i.to_string()]).collect();
+        let mut context = report(input);
+        context.set_page(PageSize::A4, PageOrientation::Portrait);
+        context.set_font_dir_and_name("assets/fonts/Roboto/static", "Roboto");
+        context.footor.push(Box::new(FooterR1 {}));
+        context.exec().unwrap();
+        let buffer = context.get_buffer();
+        assert_eq!(buffer.matches("FTS\nTL\t10\t285\tfooter\nFTE\n").count(), 2);
+        // the footer of each page is not reported below footer_vpos
+        assert_eq!(context.validate_buffer(), vec![]);
+    }
+
+    #[test]
+    fn report1_exec_errors() {
+        let mut context = report(vec![vec!["1".to_string()]]);
+        context.footer_vpos = 0.0;
+        assert!(matches!(
+            context.exec(),
+            Err(ReportError::FooterPositionNotSet)
+        ));
+
+        let mut context = report(vec![vec!["1".to_string()], vec!["abc".to_string()]]);
+        match context.exec() {
+            Err(ReportError::Band { band, cur_line, .. }) => {
+                assert_eq!(band, BandKind::Detail);
+                assert_eq!(cur_line, 1);
+            }
+            _ => panic!("band error must be returned"),
+        }
+        // the band is put back so the context can be reused
+        assert_eq!(context.detail.len(), 1);
+
+        fn send_sync<T: Send + Sync>(_: &T) {}
+        send_sync(&context.exec().unwrap_err());
+    }
+
+    #[test]
+    fn report1_merge() {
+        let mut reports = Vec::new();
+        for rows in [2, 3] {
+            let input = (0..rows).map(|i| vec![i.to_string()]).collect();
+            let mut context = report(input);
+            context.footer_vpos = 16.0;
+            context.set_page(PageSize::A4, PageOrientation::Portrait);
+            context.page_header.push(Box::new(PageHeaderR1 {}));
+            context.exec().unwrap();
+            reports.push(context);
+        }
+        let mut merged = Context::merge(&reports);
+        let headers: Vec<String> = merged
+            .commands()
+            .unwrap()
+            .into_iter()
+            .filter_map(|c| match c {
+                BufferCommand::TextLeft { y: 5.0, text, .. } => Some(text),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(
+            headers,
+            vec!["Page 2", "Page 2", "Page 3", "Page 3", "Page 3"]
+        );
+        let new_pages = merged
+            .commands()
+            .unwrap()
+            .into_iter()
+            .filter(|c| *c == BufferCommand::NewPage)
+            .count();
+        assert_eq!(new_pages, 4);
+        merged.convert().unwrap();
+        assert_eq!(merged.pages.len(), 5);
+    }
+
+    #[test]
+    fn report1_merge_resets_state() {
+        let mut reports = Vec::new();
+        for styled in [true, false] {
+            let mut context = report(vec![vec!["1".to_string()]]);
+            context.set_page(PageSize::A4, PageOrientation::Portrait);
+            context.set_font_dir_and_name("assets/fonts/Roboto/static", "Roboto");
+            if styled {
+                context.set_font_style(FontStyle::Bold);
+                context.set_char_spacing(Length::Pt(2.0));
+            }
+            context.exec().unwrap();
+            reports.push(context);
+        }
+        let mut merged = Context::merge(&reports);
+        assert!(merged.get_buffer().contains("RST\nNP\n"));
+        merged.convert().unwrap();
+        assert_eq!(merged.pages.len(), 2);
+        assert_eq!(merged.font_style, FontStyle::Regular);
+        assert_eq!(merged.char_spacing, 0.0);
+        let spaced = merged.pages[1].ops.iter().any(|op| {
+            matches!(op, printpdf::Op::SetCharacterSpacing { multiplier } if *multiplier != 0.0)
+        });
+        assert!(!spaced);
+    }
+
+    #[test]
+    fn report1_merge_pending_page_change() {
+        // the first report asks for landscape on a page it never starts
+        let mut first = Context::new();
+        first.set_page(PageSize::A4, PageOrientation::Portrait);
+        first.set_font_dir_and_name("assets/fonts/Roboto/static", "Roboto");
+        first.write_text(10.0, 20.0, "first");
+        first.set_next_page(PageSize::A4, PageOrientation::Landscape);
+        let mut second = Context::new();
+        second.set_font_dir_and_name("assets/fonts/Roboto/static", "Roboto");
+        second.write_text(10.0, 20.0, "second");
+        let mut merged = Context::merge(&[first, second]);
+        merged.convert().unwrap();
+        assert_eq!(merged.pages.len(), 2);
+        assert_eq!((merged.page_width, merged.page_height), (210.0, 297.0));
+        assert_eq!(merged.next_page_size, None);
+    }
+
+    #[test]
+    fn report1_merge_font_paths() {
+        // one font directory spelt three ways is one font, not a conflict
+        let dir = "assets/fonts/Roboto/static";
+        let absolute = std::fs::canonicalize(dir).unwrap();
+        let mut reports = Vec::new();
+        for spelling in [
+            dir.to_string(),
+            format!("{}/", dir),
+            absolute.display().to_string(),
+        ] {
+            let mut context = Context::new();
+            context.set_page(PageSize::A4, PageOrientation::Portrait);
+            context.set_font_dir_and_name(&spelling, "Roboto");
+            context.write_text(10.0, 20.0, &spelling);
+            assert!(context.font_errors.is_empty());
+            reports.push(context);
+        }
+        let mut merged = Context::merge(&reports);
+        merged.convert().unwrap();
+        assert_eq!(merged.pages.len(), 3);
+        assert_eq!(merged.font_families.len(), 1);
+    }
+
+    #[test]
+    fn report1_page_change() {
+        let mut context = Context::new();
+        context.set_page(PageSize::A4, PageOrientation::Portrait);
+        context.footer_vpos = 280.0;
+        context.input = vec![vec!["0".to_string()]; 3];
+        context.detail.push(Box::new(DetailR2 {}));
+        context.page_header.push(Box::new(PageHeaderR2 {}));
+        context.exec().unwrap();
+        let headers: Vec<String> = context
+            .commands()
+            .unwrap()
+            .into_iter()
+            .filter_map(|c| match c {
+                BufferCommand::TextLeft { text, .. } => Some(text),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(headers, vec!["210x297 280", "297x210 193", "297x210 193"]);
+        assert_eq!(context.next_page_size, None);
+        assert!(context.get_buffer().contains("PC\tA4\tLandscape\n"));
+        context.convert().unwrap();
+        assert_eq!(context.pages.len(), 3);
+    }
+
+    #[test]
+    fn report1_margins() {
+        let mut context = report(vec![vec!["1".to_string()]]);
+        context.set_page(PageSize::Letter, PageOrientation::Portrait);
+        context.set_margins(Margins::new(10.0, 15.0, 20.0, 25.0));
+        context.set_footer_height(9.0);
+        assert_eq!(context.printable_width(), 176.0);
+        assert_eq!(context.printable_height(), 249.0);
+        context.exec().unwrap();
+        assert_eq!(context.footer_vpos, 240.0);
+        context.write_rect(0.0, 0.0, context.printable_width(), 5.0, false);
+        assert!(context.get_buffer().contains("TL\t35\t14\t1\n"));
+        assert!(context.get_buffer().contains("R\t25\t10\t201\t15\tN\n"));
+    }
+
+    #[test]
+    fn report1_units() {
+        assert_eq!(Length::In(1.0).mm(), 25.4);
+        assert_eq!(Length::Pt(72.0).inch(), 1.0);
+        assert_eq!(Length::Mm(25.4).pt(), 72.0);
+        let mut context = Context::new();
+        context.set_margins(Margins::uniform(Length::In(1.0)));
+        context.write_text(Length::In(1.0), Length::Pt(72.0), "a");
+        context.write_text(25.4, 25.4, "b");
+        context.set_outline_width(Length::In(1.0 / 72.0));
+        assert_eq!(
+            context.get_buffer(),
+            "TL\t50.8\t50.8\ta\nTL\t50.8\t50.8\tb\nOS\t1\n"
+        );
+    }
+
+    #[test]
+    fn report1_font_metrics() {
+        let mut context = Context::new();
+        context.footer_vpos = 280.0;
+        context.input = vec![vec!["Total".to_string()]];
+        context.detail.push(Box::new(DetailR3 {}));
+        assert!(matches!(
+            context.string_width("Total"),
+            Err(ConvertErrorKind::UnknownFont(_))
+        ));
+        context.set_font_dir_and_name("assets/fonts/Roboto/static", "Roboto");
+        context.set_font_size(10);
+        assert!(context.string_width("Total").is_ok());
+        context.exec().unwrap();
+        context.convert().unwrap();
+        assert_eq!(context.genpdffonts.len(), 1);
+    }
+}
@@ -38,10 +38,10 @@ mod simple1 {
         context.detail.push(Box::new(DetailS1 {}));
         context.page_header.push(Box::new(PageHeaderS1 {}));
         context.report_summary.push(Box::new(ReportSummaryS1 {}));
-        context.exec();
-        context.convert();
-        context.save("temp/simple1.pdf");
-        context.write_buffer("temp/simple1.txt");
+        context.exec().unwrap();
+        context.convert().unwrap();
+        context.save("temp/simple1.pdf").unwrap();
+        context.write_buffer("temp/simple1.txt").unwrap();
 
     }
 
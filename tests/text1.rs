@@ -0,0 +1,561 @@
+#[cfg(test)]
+mod text1 {
+    use rustreport::*;
+
+    /// Checks that every buffer line serializes back to itself.
+    fn round_trip_buffer(context: &Context) {
+        for line in context.buffer.iter() {
+            let command = BufferCommand::parse(line).unwrap();
+            assert_eq!(command.serialize() + "\n", *line);
+        }
+    }
+
+    /// The texts drawn on a page, in order.
+    fn page_texts(page: &printpdf::PdfPage) -> Vec<String> {
+        page.ops
+            .iter()
+            .filter_map(|op| match op {
+                printpdf::Op::WriteText { text, .. } => Some(text.clone()),
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn text1_font_path() {
+        let mut context = Context::new();
+        context.set_page(PageSize::A4, PageOrientation::Portrait);
+        context.set_font_dir_and_name("temp/no_such_dir", "Roboto");
+        assert_eq!(context.font_errors.len(), 1);
+        assert_eq!(context.font_errors[0].line, 2);
+        assert_eq!(context.font_errors[0].opcode, "FF");
+        let e = context.clone().convert().unwrap_err();
+        assert_eq!((e.line, e.opcode.as_str()), (2, "FF"));
+        context.add_font_path("assets/fonts/Roboto/static");
+        context.convert().unwrap();
+    }
+
+    #[test]
+    fn text1_align() {
+        let mut context = Context::new();
+        context.set_page(PageSize::A4, PageOrientation::Portrait);
+        context.set_font_dir_and_name("assets/fonts/Roboto/static", "Roboto");
+        context.set_font_size(12);
+        context.write_text_center(105.0, 20.0, "Sales Report");
+        context.write_text_justify(10.0, 30.0, 200.0, "Code  Name\tAmount");
+        assert_eq!(context.buffer[3], "TM\t105\t20\tSales Report\n");
+        assert_eq!(context.buffer[4], "TJ\t10\t30\t200\tCode  Name\\tAmount\n");
+        round_trip_buffer(&context);
+        context.convert().unwrap();
+        let texts = page_texts(&context.pages[0]);
+        assert_eq!(texts, vec!["Sales Report", "Code", "Name", "Amount"]);
+    }
+
+    #[test]
+    fn text1_box() {
+        let mut context = Context::new();
+        context.set_page(PageSize::A4, PageOrientation::Portrait);
+        context.set_font_dir_and_name("assets/fonts/Roboto/static", "Roboto");
+        context.set_font_size(12);
+        context.set_line_spacing(1.5);
+        context.set_text_align(TextAlign::Right);
+        let text = "Sales by region\n東京、大阪";
+        context.write_text_box(10.0, 20.0, 50.0, text);
+        let command = BufferCommand::parse(&context.buffer[3]).unwrap();
+        assert_eq!(command.serialize() + "\n", context.buffer[3]);
+        match command {
+            BufferCommand::TextBox {
+                x,
+                y,
+                width,
+                line_height,
+                align,
+                text: t,
+            } => {
+                assert_eq!((x, y, width), (10.0, 20.0, 50.0));
+                assert!((line_height - 6.35).abs() < 1e-9);
+                assert_eq!(align, TextAlign::Right);
+                assert_eq!(t, text);
+            }
+            _ => panic!("TB expected"),
+        }
+        assert_eq!("J".parse::<TextAlign>(), Ok(TextAlign::Justify));
+        assert!("X".parse::<TextAlign>().is_err());
+        context.convert().unwrap();
+        let height = context.measure_text_box(50.0, text).unwrap();
+        assert!((height - 2.0 * 6.35).abs() < 1e-9);
+    }
+
+    #[test]
+    fn text1_font_style() {
+        let dir = "temp/text1_font_style";
+        std::fs::create_dir_all(dir).unwrap();
+        std::fs::copy(
+            "assets/fonts/Roboto/static/Roboto-Regular.ttf",
+            format!("{}/Roboto-Regular.ttf", dir),
+        )
+        .unwrap();
+        std::fs::copy(
+            "assets/fonts/Roboto/static/bold/Roboto_CondensedBold-Regular.ttf",
+            format!("{}/Roboto-Bold.ttf", dir),
+        )
+        .unwrap();
+        let mut context = Context::new();
+        context.set_page(PageSize::A4, PageOrientation::Portrait);
+        context.set_font_dir_and_name(dir, "Roboto");
+        context.set_font_size(10);
+        context.set_font_style(FontStyle::Bold);
+        assert!(context.string_width("Total").is_ok());
+        context.write_text(10.0, 20.0, "Total");
+        context.set_font_style(FontStyle::Italic);
+        context.write_text(10.0, 30.0, "note");
+        assert_eq!(context.buffer[3], "FST\tBold\n");
+        assert_eq!(
+            BufferCommand::parse(&context.buffer[5]).unwrap(),
+            BufferCommand::FontStyle(FontStyle::Italic)
+        );
+        context.convert().unwrap();
+        let mut keys: Vec<&String> = context.font_files.keys().collect();
+        keys.sort();
+        // there is no italic face, it falls back to the regular one
+        assert_eq!(keys, vec!["Roboto", "Roboto-Bold", "Roboto-Italic"]);
+        assert_eq!(
+            context.font_files["Roboto-Italic"],
+            context.font_files["Roboto"]
+        );
+        assert_ne!(
+            context.font_files["Roboto-Bold"],
+            context.font_files["Roboto"]
+        );
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn text1_font_fallback() {
+        let mut context = Context::new();
+        context.set_page(PageSize::A4, PageOrientation::Portrait);
+        context.set_font_dir_and_name("assets/fonts/Roboto/static/bold", "Roboto_CondensedBold");
+        context.set_font_dir_and_name("assets/fonts/Roboto/static", "Roboto");
+        context.set_font_fallback("Roboto", &["Roboto_CondensedBold"]);
+        context.set_font_size(10);
+        assert_eq!(context.buffer[3], "FB\tRoboto\tRoboto_CondensedBold\n");
+        let command = BufferCommand::parse(&context.buffer[3]).unwrap();
+        assert_eq!(command.serialize() + "\n", context.buffer[3]);
+        assert!(matches!(
+            BufferCommand::parse("FB\tRoboto\t"),
+            Err(ParseError::InvalidField { index: 2, .. })
+        ));
+        let width = context.string_width("Total 東京").unwrap();
+        context.write_text_right(100.0, 20.0, "Total 東京");
+        context.convert().unwrap();
+        // neither font has the kanji, so they stay with the first font
+        let texts = page_texts(&context.pages[0]);
+        assert_eq!(texts, vec!["Total 東京"]);
+        assert_eq!(context.string_width("Total 東京").unwrap(), width);
+
+        let mut context = Context::new();
+        context.set_page(PageSize::A4, PageOrientation::Portrait);
+        context.set_font_dir_and_name("assets/fonts/Noto_Sans_JP/static", "NotoSansJP");
+        context.set_font_size(10);
+        let kanji = context.string_width("東京").unwrap();
+        context.set_font_dir_and_name("assets/fonts/Roboto/static", "Roboto");
+        let latin = context.string_width("Total ").unwrap();
+        context.set_font_fallback("Roboto", &["NotoSansJP"]);
+        let width = context.string_width("Total 東京").unwrap();
+        assert!((width - (latin + kanji)).abs() < 1e-9);
+        context.write_text_right(100.0, 20.0, "Total 東京");
+        // an empty list removes the fallbacks again
+        context.set_font_fallback("Roboto", &[]);
+        assert_eq!(context.buffer[6], "FB\tRoboto\n");
+        assert_eq!(
+            BufferCommand::parse(&context.buffer[6]),
+            Ok(BufferCommand::FontFallback {
+                name: "Roboto".to_string(),
+                fallbacks: vec![],
+            })
+        );
+        round_trip_buffer(&context);
+        context.write_text(10.0, 30.0, "東京");
+        context.convert().unwrap();
+        let ops = &context.pages[0].ops;
+        let runs: Vec<(String, printpdf::FontId)> = ops
+            .iter()
+            .filter_map(|op| match op {
+                printpdf::Op::WriteText { text, font, .. } => Some((text.clone(), font.clone())),
+                _ => None,
+            })
+            .collect();
+        let roboto = context.font_files["Roboto"].clone();
+        let noto = context.font_files["NotoSansJP"].clone();
+        assert_eq!(
+            runs,
+            vec![
+                ("Total ".to_string(), roboto.clone()),
+                ("東京".to_string(), noto),
+                ("東京".to_string(), roboto),
+            ]
+        );
+        let cursors: Vec<f32> = ops
+            .iter()
+            .filter_map(|op| match op {
+                printpdf::Op::SetTextCursor { pos } => Some(pos.x.0),
+                _ => None,
+            })
+            .collect();
+        // right aligned with each run measured in its own font
+        assert!((cursors[0] - Length::Mm(100.0 - width).pt() as f32).abs() < 1e-3);
+        assert!((cursors[1] - Length::Mm(100.0 - kanji).pt() as f32).abs() < 1e-3);
+
+        let mut context = Context::new();
+        context.set_font_fallback("Roboto", &["NotoSansJP"]);
+        let issues = context.validate_buffer();
+        assert_eq!(
+            issues[0].kind,
+            validate::IssueKind::UnknownFont("NotoSansJP".to_string())
+        );
+    }
+
+    #[test]
+    fn text1_font_cache() {
+        let regular = "assets/fonts/Roboto/static/Roboto-Regular.ttf";
+        let mut context = Context::new();
+        context.set_page(PageSize::A4, PageOrientation::Portrait);
+        context.set_font_dir_and_name("assets/fonts/Roboto/static", "Roboto");
+        context.set_font_dir_and_name("assets/fonts/Roboto/static/", "Roboto");
+        context.set_font_file("Roboto", FontStyle::Regular, regular);
+        context.set_font_file("Alias", FontStyle::Regular, regular);
+        context.set_font_size(10);
+        context.write_text(10.0, 20.0, "Total");
+        assert_eq!(context.font_families.len(), 1);
+        context.convert().unwrap();
+        // the same files are added to the font cache once
+        assert_eq!(context.font_families.len(), 1);
+
+        let dir = "temp/text1_font_cache";
+        std::fs::create_dir_all(dir).unwrap();
+        std::fs::copy(
+            "assets/fonts/Roboto/static/Roboto_Condensed-Regular.ttf",
+            format!("{}/Roboto-Regular.ttf", dir),
+        )
+        .unwrap();
+        let mut context = Context::new();
+        context.set_page(PageSize::A4, PageOrientation::Portrait);
+        context.set_font_dir_and_name("assets/fonts/Roboto/static", "Roboto");
+        context.set_font_dir_and_name(dir, "Roboto");
+        let e = context.clone().convert().unwrap_err();
+        assert_eq!((e.line, e.opcode.as_str()), (3, "FF"));
+        assert!(
+            matches!(e.kind, ConvertErrorKind::FontConflict { ref name, .. } if name == "Roboto")
+        );
+        let mut context = Context::new();
+        context.set_page(PageSize::A4, PageOrientation::Portrait);
+        context.set_font_dir_and_name("assets/fonts/Roboto/static", "Roboto");
+        context.set_font_file(
+            "Roboto",
+            FontStyle::Regular,
+            &format!("{}/Roboto-Regular.ttf", dir),
+        );
+        let e = context.convert().unwrap_err();
+        assert_eq!(e.opcode, "FP");
+        assert!(matches!(e.kind, ConvertErrorKind::FontConflict { .. }));
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn text1_font_source() {
+        static REGULAR: &[u8] = include_bytes!("../assets/fonts/Roboto/static/Roboto-Regular.ttf");
+        static BOLD: &[u8] =
+            include_bytes!("../assets/fonts/Roboto/static/bold/Roboto_CondensedBold-Regular.ttf");
+        let mut context = Context::new();
+        context.set_page(PageSize::A4, PageOrientation::Portrait);
+        context.set_font_file(
+            "Brand",
+            FontStyle::Regular,
+            "assets/fonts/Roboto/static/Roboto_Condensed-Regular.ttf",
+        );
+        context.set_font_bytes("Embedded", FontStyle::Regular, REGULAR);
+        context.set_font_bytes("Embedded", FontStyle::Bold, BOLD);
+        context.set_font_size(10);
+        assert!(context.string_width("Total").is_ok());
+        context.set_font_style(FontStyle::Bold);
+        context.write_text(10.0, 20.0, "Total");
+        context.set_font("Brand");
+        context.write_text(10.0, 30.0, "Brand");
+        assert_eq!(
+            &context.buffer[1..4],
+            &[
+                "FP\tBrand\tRegular\tassets/fonts/Roboto/static/Roboto_Condensed-Regular.ttf\n",
+                "FM\tEmbedded\tRegular\n",
+                "FM\tEmbedded\tBold\n"
+            ]
+        );
+        round_trip_buffer(&context);
+        let buffer = context.get_buffer();
+        context.convert().unwrap();
+        let mut keys: Vec<&String> = context.font_files.keys().collect();
+        keys.sort();
+        assert_eq!(
+            keys,
+            vec!["Brand", "Brand-Bold", "Embedded", "Embedded-Bold"]
+        );
+
+        // the data of FM lines has to be provided by the converting program
+        let mut other = Context::new();
+        other.buffer = buffer.lines().map(|l| format!("{}\n", l)).collect();
+        match other.clone().convert() {
+            Err(e) => assert!(
+                matches!(e.kind, ConvertErrorKind::UnknownFont(ref key) if key == "Embedded")
+            ),
+            Ok(_) => panic!("FM without data must fail"),
+        }
+        other.add_font_bytes("Embedded", FontStyle::Regular, REGULAR);
+        other.add_font_bytes("Embedded", FontStyle::Bold, BOLD);
+        other.convert().unwrap();
+    }
+
+    #[test]
+    fn text1_direction() {
+        let mut context = Context::new();
+        context.set_page(PageSize::A4, PageOrientation::Portrait);
+        context.set_font_dir_and_name("assets/fonts/Roboto/static", "Roboto");
+        context.set_font_size(10);
+        context.write_text_rotated(20.0, 100.0, 90.0, "Side");
+        context.write_text_vertical(190.0, 20.0, "第1期、売上");
+        context.write_text_vertical(180.0, 20.0, "コード");
+        assert_eq!(context.buffer[3], "TRT\t20\t100\t90\tSide\n");
+        assert_eq!(context.buffer[4], "TV\t190\t20\t第1期、売上\n");
+        round_trip_buffer(&context);
+        let em = Length::Pt(10.0).mm();
+        let height = context.measure_text_vertical("第1期、売上").unwrap();
+        let digit = context.string_width("1").unwrap();
+        assert!((height - (5.0 * em + digit)).abs() < 1e-9);
+        let height = context.measure_text_vertical("コード").unwrap();
+        assert!((height - 3.0 * em).abs() < 1e-9);
+        context.convert().unwrap();
+        let ops = &context.pages[0].ops;
+        let matrices: Vec<[f32; 6]> = ops
+            .iter()
+            .filter_map(|op| match op {
+                printpdf::Op::SetTextMatrix {
+                    matrix: printpdf::TextMatrix::Raw(m),
+                } => Some(*m),
+                _ => None,
+            })
+            .collect();
+        // "Side" turned counter-clockwise, then "1" and "ー" turned clockwise
+        assert_eq!(matrices.len(), 3);
+        assert!(matrices[0][0].abs() < 1e-6 && matrices[0][1] == 1.0);
+        assert!((matrices[0][5] - Length::Mm(197.0).pt() as f32).abs() < 1e-3);
+        assert!(matrices[1][0].abs() < 1e-6 && matrices[1][1] == -1.0);
+        assert!(matrices[2][0].abs() < 1e-6 && matrices[2][1] == -1.0);
+        let texts = page_texts(&context.pages[0]);
+        assert_eq!(
+            texts,
+            vec!["Side", "第", "1", "期", "、", "売", "上", "コ", "ー", "ド"]
+        );
+    }
+
+    #[test]
+    fn text1_spacing() {
+        let mut context = Context::new();
+        context.set_page(PageSize::A4, PageOrientation::Portrait);
+        context.set_font_dir_and_name("assets/fonts/Roboto/static", "Roboto");
+        context.set_font_size(10);
+        let plain = context.string_width("ab c").unwrap();
+        context.set_char_spacing(Length::Pt(1.0));
+        context.set_word_spacing(Length::Pt(2.0));
+        context.set_horizontal_scaling(50.0);
+        context.set_leading(Length::Pt(12.0));
+        assert_eq!(
+            &context.buffer[3..7],
+            &["CS\t1\n", "WS\t2\n", "HS\t50\n", "LD\t12\n"]
+        );
+        round_trip_buffer(&context);
+        // 4 characters and 1 space, without the spacing after the last one
+        let width = context.string_width("ab c").unwrap();
+        let expected = plain * 0.5 + Length::Pt((4.0 + 2.0 - 1.0) * 0.5).mm();
+        assert!((width - expected).abs() < 1e-9);
+        let height = context.measure_text_box(1000.0, "a\nb").unwrap();
+        assert!((height - Length::Pt(24.0).mm()).abs() < 1e-9);
+
+        context.write_text_right(100.0, 20.0, "ab c");
+        context.convert().unwrap();
+        let ops = &context.pages[0].ops;
+        assert!(ops.contains(&printpdf::Op::SetCharacterSpacing { multiplier: 1.0 }));
+        assert!(ops.contains(&printpdf::Op::SetHorizontalScaling { percent: 50.0 }));
+        let cursors = ops.iter().filter_map(|op| match op {
+            printpdf::Op::SetTextCursor { pos } => Some(pos.x.0),
+            _ => None,
+        });
+        let texts: Vec<(f32, String)> = cursors
+            .zip(ops.iter().filter_map(|op| match op {
+                printpdf::Op::WriteText { text, .. } => Some(text.clone()),
+                _ => None,
+            }))
+            .collect();
+        // the words are placed one by one to apply the word spacing
+        assert_eq!(texts.len(), 2);
+        assert_eq!(texts[0].1, "ab ");
+        assert_eq!(texts[1].1, "c");
+        let start = Length::Mm(100.0 - width).pt() as f32;
+        assert!((texts[0].0 - start).abs() < 1e-3);
+    }
+
+    #[test]
+    fn text1_leading() {
+        let mut context = Context::new();
+        context.set_page(PageSize::A4, PageOrientation::Portrait);
+        context.set_font_dir_and_name("assets/fonts/Roboto/static", "Roboto");
+        context.set_font_size(10);
+        context.write_text_box(10.0, 20.0, 100.0, "a\nb");
+        context.set_leading(Length::Pt(20.0));
+        context.write_text_box(10.0, 100.0, 100.0, "a\nb");
+        context.convert().unwrap();
+        let ops = &context.pages[0].ops;
+        assert!(!ops
+            .iter()
+            .any(|op| matches!(op, printpdf::Op::SetLineHeight { .. })));
+        let baselines: Vec<f32> = ops
+            .iter()
+            .filter_map(|op| match op {
+                printpdf::Op::SetTextCursor { pos } => Some(pos.y.0),
+                _ => None,
+            })
+            .collect();
+        // 1.2 times the font size, then the leading
+        assert_eq!(baselines.len(), 4);
+        assert!((baselines[0] - baselines[1] - 12.0).abs() < 1e-3);
+        assert!((baselines[2] - baselines[3] - 20.0).abs() < 1e-3);
+    }
+
+    #[test]
+    fn text1_decoration() {
+        let bytes = std::fs::read("assets/fonts/Roboto/static/Roboto-Regular.ttf").unwrap();
+        let metrics = LineMetrics::from_font(&bytes);
+        assert_ne!(metrics, LineMetrics::default());
+        assert!(metrics.underline_position < 0.0 && metrics.underline_thickness > 0.0);
+        assert!(metrics.strikeout_position > 0.0 && metrics.strikeout_thickness > 0.0);
+        assert_eq!(
+            LineMetrics::from_font(b"not a font"),
+            LineMetrics::default()
+        );
+
+        let mut context = Context::new();
+        context.set_page(PageSize::A4, PageOrientation::Portrait);
+        context.set_font_dir_and_name("assets/fonts/Roboto/static", "Roboto");
+        context.set_font_size(10);
+        context.set_text_decoration(TextDecoration::DoubleUnderline);
+        context.write_text(10.0, 20.0, "Total");
+        context.set_text_decoration(TextDecoration::Strikethrough);
+        context.write_text_justify(10.0, 30.0, 100.0, "was 100");
+        context.write_text_vertical(190.0, 20.0, "合計");
+        context.set_text_decoration(TextDecoration::None);
+        context.write_text(10.0, 40.0, "plain");
+        assert_eq!(context.buffer[3], "TD\tDoubleUnderline\n");
+        round_trip_buffer(&context);
+        context.convert().unwrap();
+        let polygons: Vec<Vec<(f32, f32)>> = context.pages[0]
+            .ops
+            .iter()
+            .filter_map(|op| match op {
+                printpdf::Op::DrawPolygon { polygon } => Some(
+                    polygon.rings[0]
+                        .iter()
+                        .map(|(p, _)| (Length::Pt(p.x.0 as f64).mm() as f32, p.y.0))
+                        .collect(),
+                ),
+                _ => None,
+            })
+            .collect();
+        // two underlines below the baseline, one line through the justified
+        // span, none for the vertical text
+        assert_eq!(polygons.len(), 3);
+        let baseline = Length::Mm(297.0 - 20.0).pt() as f32;
+        assert!(polygons[0][0].1 < baseline && polygons[1][0].1 < polygons[0][0].1);
+        // the underline position is the top of the line
+        let top = baseline + metrics.underline_position as f32 * 10.0;
+        assert!((polygons[0][0].1 - top).abs() < 1e-2);
+        let thickness = metrics.underline_thickness as f32 * 10.0;
+        assert!((polygons[0][2].1 - (top - thickness)).abs() < 1e-2);
+        assert!((polygons[2][0].0 - 10.0).abs() < 1e-3);
+        assert!((polygons[2][1].0 - 100.0).abs() < 1e-3);
+        let strikeout =
+            Length::Mm(297.0 - 30.0).pt() as f32 + metrics.strikeout_position as f32 * 10.0;
+        assert!((polygons[2][0].1 - strikeout).abs() < 1e-2);
+    }
+
+    #[test]
+    fn text1_fit() {
+        let name = "Long Customer Name Trading Ltd";
+        let mut context = Context::new();
+        context.set_page(PageSize::A4, PageOrientation::Portrait);
+        context.set_font_dir_and_name("assets/fonts/Roboto/static", "Roboto");
+        context.set_font_size(10);
+        context.set_char_spacing(Length::Pt(1.0));
+        context.write_text_fit(10.0, 20.0, 3.0, TextFit::Ellipsis, name);
+        context.write_text_fit(10.0, 30.0, 3.0, TextFit::Shrink { min_size: 6 }, name);
+        context.write_text_fit(10.0, 40.0, 1000.0, TextFit::Shrink { min_size: 6 }, name);
+        context.set_text_align(TextAlign::Right);
+        context.write_text_fit(10.0, 50.0, 50.0, TextFit::Ellipsis, "ab");
+        assert_eq!(
+            context.buffer[4],
+            format!("TF\t10\t20\t3\tL\tEllipsis\t{}\n", name)
+        );
+        assert_eq!(
+            context.buffer[5],
+            format!("TF\t10\t30\t3\tL\tShrink:6\t{}\n", name)
+        );
+        round_trip_buffer(&context);
+        assert!("Shrink:x".parse::<TextFit>().is_err());
+        context.convert().unwrap();
+        assert_eq!(context.font_size, 10);
+        let ab = context.string_width("ab").unwrap();
+        let ops = &context.pages[0].ops;
+        let cursors: Vec<f32> = ops
+            .iter()
+            .filter_map(|op| match op {
+                printpdf::Op::SetTextCursor { pos } => Some(pos.x.0),
+                _ => None,
+            })
+            .collect();
+        let texts: Vec<(String, f32)> = ops
+            .iter()
+            .filter_map(|op| match op {
+                printpdf::Op::WriteText { text, size, .. } => Some((text.clone(), size.0)),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(texts.len(), 4);
+        let cut = texts[0].0.strip_suffix('…').unwrap();
+        assert!(name.starts_with(cut) && cut.len() < name.len());
+        assert_eq!(texts[0].1, 10.0);
+        // too long even at the minimum size, so also cut
+        assert!(texts[1].0.ends_with('…'));
+        assert_eq!(texts[1].1, 6.0);
+        assert_eq!(texts[2], (name.to_string(), 10.0));
+        assert_eq!(texts[3], ("ab".to_string(), 10.0));
+        assert!((cursors[3] - Length::Mm(60.0 - ab).pt() as f32).abs() < 1e-3);
+    }
+
+    #[test]
+    fn text1_subset_fonts() {
+        let mut context = Context::new();
+        assert!(context.save_options().subset_fonts);
+        assert!(context.clone().save_options().subset_fonts);
+        context.set_subset_fonts(false);
+        assert!(!context.save_options().subset_fonts);
+
+        let mut context = Context::new();
+        context.set_page(PageSize::A4, PageOrientation::Portrait);
+        context.set_font_dir_and_name("assets/fonts/Noto_Sans_JP/static", "NotoSansJP");
+        context.set_font_size(10);
+        context.write_text(10.0, 20.0, "請求書 合計 1,000円");
+        context.convert().unwrap();
+        let subset = context.get_pdf_bytes().unwrap();
+        context.set_subset_fonts(false);
+        let full = context.get_pdf_bytes().unwrap();
+        // the whole face is several MB, the glyphs of one line a few KB
+        assert!(full.len() > 1_000_000);
+        assert!(subset.len() * 20 < full.len());
+    }
+}
@@ -0,0 +1,98 @@
+#[cfg(test)]
+mod validate1 {
+    use rustreport::validate::{IssueKind, Severity};
+    use rustreport::*;
+
+    #[test]
+    fn validate1() {
+        let mut context = Context::new();
+        context.set_page(PageSize::A4, PageOrientation::Portrait);
+        context.footer_vpos = 280.0;
+        context.set_font_dir_and_name("assets/fonts/Roboto/static", "Roboto");
+        context.set_font("Roboto");
+        context.write_text(10.0, 20.0, "ok");
+        assert_eq!(context.validate_buffer(), vec![]);
+
+        context.set_font("NotLoaded");
+        context.write_text(220.0, 20.0, "outside");
+        context.write_text(10.0, 285.0, "Total !!!TOTAL!!!");
+        context.write_image(10.0, 20.0, 5.0, 5.0, "temp/no_such_image.png");
+        context.buffer.push("XX\t1\n".to_string());
+        let issues = context.validate_buffer();
+        let found: Vec<(usize, Severity, IssueKind)> = issues
+            .into_iter()
+            .map(|i| (i.line, i.severity, i.kind))
+            .collect();
+        assert_eq!(
+            found,
+            vec![
+                (
+                    5,
+                    Severity::Error,
+                    IssueKind::UnknownFont("NotLoaded".to_string())
+                ),
+                (
+                    6,
+                    Severity::Warning,
+                    IssueKind::OutsidePage { x: 220.0, y: 20.0 }
+                ),
+                (7, Severity::Warning, IssueKind::BelowFooter { y: 285.0 }),
+                (
+                    7,
+                    Severity::Warning,
+                    IssueKind::UnresolvedMarker("!!!TOTAL!!!".to_string())
+                ),
+                (
+                    8,
+                    Severity::Error,
+                    IssueKind::MissingImage("temp/no_such_image.png".to_string())
+                ),
+                (
+                    9,
+                    Severity::Error,
+                    IssueKind::Parse(ParseError::UnknownOpcode("XX".to_string()))
+                ),
+            ]
+        );
+
+        // text and FST need a font; !!! around words is not a marker
+        let mut context = Context::new();
+        context.set_font_style(FontStyle::Bold);
+        context.write_text(10.0, 20.0, "SALE!!! ends soon !!!");
+        context.write_text_right(100.0, 20.0, "!!!TOTAL!!!");
+        let found: Vec<(usize, IssueKind)> = context
+            .validate_buffer()
+            .into_iter()
+            .map(|i| (i.line, i.kind))
+            .collect();
+        assert_eq!(
+            found,
+            vec![
+                (1, IssueKind::NoFont),
+                (2, IssueKind::NoFont),
+                (3, IssueKind::NoFont),
+                (3, IssueKind::UnresolvedMarker("!!!TOTAL!!!".to_string())),
+            ]
+        );
+        assert!(matches!(
+            context.convert().unwrap_err().kind,
+            ConvertErrorKind::UnknownFont(_)
+        ));
+
+        // footer bands draw below footer_vpos on purpose
+        let mut context = Context::new();
+        context.set_page(PageSize::A4, PageOrientation::Portrait);
+        context.footer_vpos = 280.0;
+        context.set_font_dir_and_name("assets/fonts/Roboto/static", "Roboto");
+        context.push_command(BufferCommand::FooterStart);
+        context.write_text(10.0, 285.0, "footer");
+        context.push_command(BufferCommand::FooterEnd);
+        context.write_text(10.0, 290.0, "detail");
+        let found: Vec<(usize, IssueKind)> = context
+            .validate_buffer()
+            .into_iter()
+            .map(|i| (i.line, i.kind))
+            .collect();
+        assert_eq!(found, vec![(6, IssueKind::BelowFooter { y: 290.0 })]);
+    }
+}